@@ -0,0 +1,210 @@
+//! --audit-file: a JSON line appended for each run, whatever its outcome, recording who deleted
+//! what from which cluster, how and with which result. The file is opened before anything is
+//! deleted, so that a run that could not be recorded does not start.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+use crate::{
+    events::timestamp, lock, AttemptStats, ClusterResult, ClusterRun, FailureReport, Operation,
+    Opt, Outcome, TaskId,
+};
+
+/// The --audit-file, opened at startup
+static AUDIT_FILE: Mutex<Option<(PathBuf, File)>> = Mutex::new(None);
+
+/// Open the --audit-file for appending: a missing directory or a permission problem stops the
+/// run before it deletes anything
+pub fn open(opt: &Opt) -> anyhow::Result<()> {
+    if let Some(path) = &opt.audit_file {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                anyhow::anyhow!("Unable to open the --audit-file {}: {}", path.display(), e)
+            })?;
+        *lock(&AUDIT_FILE) = Some((path.clone(), file));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    started_at: String,
+    ended_at: String,
+    operator: String,
+    hostname: String,
+    operation: &'static str,
+    options: AuditOptions,
+    outcome: Outcome,
+    exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    /// Deleted by all the runs
+    deleted: u64,
+    version_conflicts: u64,
+    /// One per cluster and --plan job
+    runs: Vec<AuditRun<'a>>,
+}
+
+/// The options deciding how the documents are deleted
+#[derive(Serialize)]
+struct AuditOptions {
+    dry_run: bool,
+    requests_per_second: Option<f64>,
+    throttle_schedule: Option<String>,
+    auto_throttle: bool,
+    abort_on_conflict: bool,
+    max_conflicts: Option<u64>,
+    resubmit_on_conflicts: bool,
+    max_docs: Option<u64>,
+    slices: Option<String>,
+    max_retries: Option<u32>,
+    force_retry: bool,
+    strict_counters: bool,
+}
+
+#[derive(Serialize)]
+struct AuditRun<'a> {
+    /// Without its credentials
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster_uuid: Option<&'a str>,
+    /// Line of the --plan job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<usize>,
+    index: &'a str,
+    /// Resolved from the index patterns, empty when the run stopped before
+    indices: &'a [String],
+    query: Option<&'a serde_json::Value>,
+    outcome: Outcome,
+    exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    task_ids: &'a [TaskId],
+    deleted: u64,
+    version_conflicts: u64,
+    per_attempt: &'a [AttemptStats],
+    failures: &'a [FailureReport],
+}
+
+/// Append the record of the runs, from the single end of the run (the Ctrl-C handler included).
+/// Errors are reported, the run is over.
+pub fn record(
+    opt: &Opt,
+    runs: &[ClusterRun],
+    results: &[ClusterResult],
+    overall: &ClusterResult,
+    started: SystemTime,
+) {
+    let mut audit_file = lock(&AUDIT_FILE);
+    let (path, file) = match &mut *audit_file {
+        Some(audit_file) => audit_file,
+        None => return,
+    };
+    let record = AuditRecord {
+        started_at: timestamp(started),
+        ended_at: timestamp(SystemTime::now()),
+        operator: operator(opt),
+        hostname: hostname(),
+        operation: Operation::of(opt).name(),
+        options: AuditOptions {
+            dry_run: opt.dry_run,
+            requests_per_second: opt.requests_per_second,
+            throttle_schedule: opt.throttle_schedule.as_ref().map(ToString::to_string),
+            auto_throttle: opt.auto_throttle,
+            abort_on_conflict: opt.abort_on_conflict,
+            max_conflicts: opt.max_conflicts,
+            resubmit_on_conflicts: opt.resubmit_on_conflicts,
+            max_docs: opt.max_docs,
+            slices: opt.slices.clone(),
+            max_retries: opt.max_retries,
+            force_retry: opt.force_retry,
+            strict_counters: opt.strict_counters,
+        },
+        outcome: overall.outcome,
+        exit_code: overall.exit_code,
+        error: overall.error.as_deref(),
+        deleted: overall.summary.deleted,
+        version_conflicts: overall.summary.version_conflicts,
+        runs: runs
+            .iter()
+            .zip(results)
+            .map(|(run, result)| AuditRun {
+                url: &run.url,
+                cluster: result.summary.cluster.as_deref(),
+                cluster_uuid: result.summary.cluster_uuid.as_deref(),
+                job: run.job,
+                index: &run.index,
+                indices: &result.summary.indices,
+                query: run.query.as_ref(),
+                outcome: result.outcome,
+                exit_code: result.exit_code,
+                error: result.error.as_deref(),
+                task_ids: &result.summary.task_ids,
+                deleted: result.summary.deleted,
+                version_conflicts: result.summary.version_conflicts,
+                per_attempt: &result.summary.per_attempt,
+                failures: &result.summary.failures,
+            })
+            .collect(),
+    };
+    let written = serde_json::to_string(&record)
+        .map_err(std::io::Error::from)
+        .and_then(|line| {
+            writeln!(file, "{}", line)?;
+            file.sync_data()
+        });
+    if let Err(e) = written {
+        eprintln!(
+            "Error: unable to write the audit record to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// --operator, else the user running the command
+fn operator(opt: &Opt) -> String {
+    opt.operator
+        .clone()
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .or_else(|| {
+            let output = std::process::Command::new("whoami").output().ok()?;
+            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !name.is_empty()).then_some(name)
+        })
+        .unwrap_or_else(|| "?".to_string())
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: gethostname writes at most the length of the buffer it is given
+    let ok = unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } == 0;
+    let len = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    if ok && len > 0 {
+        String::from_utf8_lossy(&name[..len]).into_owned()
+    } else {
+        std::env::var("HOSTNAME").unwrap_or_else(|_| "?".to_string())
+    }
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "?".to_string())
+}
@@ -0,0 +1,473 @@
+//! Minimal AWS SigV4 request signing for Amazon OpenSearch Service / Elasticsearch domains
+//! using IAM authentication.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{header::HeaderValue, Client, Request};
+use ring::{digest, hmac};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+const SERVICE: &str = "es";
+const IMDS_URL: &str = "http://169.254.169.254";
+/// Credentials without an expiration (env, profile) are resolved again after this delay
+/// so that rotated profiles are picked up during long runs.
+const STATIC_CREDENTIALS_TTL: Duration = Duration::from_secs(15 * 60);
+/// Refresh expiring credentials this long before they actually expire.
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    refresh_at: SystemTime,
+}
+
+pub struct SigV4Signer {
+    region: String,
+    credentials: Mutex<Option<Credentials>>,
+    imds: Client,
+}
+
+impl SigV4Signer {
+    pub fn new(region: String) -> anyhow::Result<SigV4Signer> {
+        Ok(SigV4Signer {
+            region,
+            credentials: Mutex::new(None),
+            imds: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(2))
+                .no_proxy()
+                .build()?,
+        })
+    }
+
+    /// Sign the request in place: adds the `x-amz-*` and `Authorization` headers.
+    pub async fn sign(&self, request: &mut Request) -> anyhow::Result<()> {
+        let credentials = self.credentials().await?;
+        let now = SystemTime::now();
+        let (date, amz_date) = amz_dates(now);
+        let payload_hash = payload_hash(request);
+
+        let url = request.url();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        let signed_headers = signed_headers.join(";");
+
+        let canonical_request = canonical_request(request, &headers, &payload_hash);
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, SERVICE);
+        let string_to_sign = string_to_sign(&amz_date, &scope, &canonical_request);
+        let signature = signature(&credentials.secret_access_key, &scope, &string_to_sign);
+
+        let request_headers = request.headers_mut();
+        for (name, value) in headers.into_iter().skip(1) {
+            let mut value = HeaderValue::from_str(&value)?;
+            value.set_sensitive(name == "x-amz-security-token");
+            request_headers.insert(name, value);
+        }
+        let mut authorization = HeaderValue::from_str(&format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ))?;
+        authorization.set_sensitive(true);
+        request_headers.insert(reqwest::header::AUTHORIZATION, authorization);
+        Ok(())
+    }
+
+    /// Current credentials, resolved again through the chain when they are about to expire.
+    async fn credentials(&self) -> anyhow::Result<Credentials> {
+        let mut cached = self.credentials.lock().await;
+        match &*cached {
+            Some(credentials) if credentials.refresh_at > SystemTime::now() => {
+                Ok(credentials.clone())
+            }
+            _ => {
+                let credentials = self.resolve_credentials().await?;
+                *cached = Some(credentials.clone());
+                Ok(credentials)
+            }
+        }
+    }
+
+    /// Standard credentials chain: environment variables, shared credentials file, then
+    /// the EC2 instance metadata service.
+    async fn resolve_credentials(&self) -> anyhow::Result<Credentials> {
+        if let Some(credentials) = env_credentials() {
+            return Ok(credentials);
+        }
+        if let Some(credentials) = profile_credentials()? {
+            return Ok(credentials);
+        }
+        self.imds_credentials().await.map_err(|e| {
+            anyhow::anyhow!(
+                "No AWS credentials found in environment, credentials file or instance metadata: {}",
+                e
+            )
+        })
+    }
+
+    async fn imds_credentials(&self) -> anyhow::Result<Credentials> {
+        let token = self
+            .imds
+            .put(format!("{}/latest/api/token", IMDS_URL))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "21600")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let base = format!("{}/latest/meta-data/iam/security-credentials/", IMDS_URL);
+        let role = self
+            .imds
+            .get(&base)
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let role = role
+            .lines()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No IAM role attached to the instance"))?;
+        let response = self
+            .imds
+            .get(format!("{}{}", base, role))
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ImdsCredentials>()
+            .await?;
+        let refresh_at = parse_rfc3339(&response.expiration)
+            .and_then(|expiration| expiration.checked_sub(EXPIRATION_MARGIN))
+            .unwrap_or_else(|| SystemTime::now() + EXPIRATION_MARGIN);
+        Ok(Credentials {
+            access_key_id: response.access_key_id,
+            secret_access_key: response.secret_access_key,
+            session_token: Some(response.token),
+            refresh_at,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ImdsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: String,
+}
+
+fn env_credentials() -> Option<Credentials> {
+    Some(Credentials {
+        access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+        secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+        session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        refresh_at: SystemTime::now() + STATIC_CREDENTIALS_TTL,
+    })
+}
+
+fn profile_credentials() -> anyhow::Result<Option<Credentials>> {
+    let path = match std::env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(".aws").join("credentials"),
+            None => return Ok(None),
+        },
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => anyhow::bail!("Unable to read {}: {}", path.display(), e),
+    };
+    let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+    let mut section = None;
+    let mut values = HashMap::new();
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = Some(line[1..line.len() - 1].trim().to_string());
+        } else if section.as_deref() == Some(profile.as_str()) {
+            if let Some((key, value)) = line.split_once('=') {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+    Ok(
+        match (
+            values.remove("aws_access_key_id"),
+            values.remove("aws_secret_access_key"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: values.remove("aws_session_token"),
+                refresh_at: SystemTime::now() + STATIC_CREDENTIALS_TTL,
+            }),
+            _ => None,
+        },
+    )
+}
+
+/// Hex encoded SHA-256 of the request body
+fn payload_hash(request: &Request) -> String {
+    hex(digest::digest(
+        &digest::SHA256,
+        request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .unwrap_or_default(),
+    )
+    .as_ref())
+}
+
+/// The canonical request signing `headers`, lowercase names sorted by name
+fn canonical_request(request: &Request, headers: &[(&str, String)], payload_hash: &str) -> String {
+    let url = request.url();
+    let mut query = url
+        .query_pairs()
+        .map(|(k, v)| (uri_encode(&k, true), uri_encode(&v, true)))
+        .collect::<Vec<_>>();
+    query.sort();
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method().as_str(),
+        uri_encode(url.path(), false),
+        query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&"),
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect::<String>(),
+        headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";"),
+        payload_hash
+    )
+}
+
+fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    )
+}
+
+/// Signature of `string_to_sign` with the key derived for the `date/region/service/aws4_request`
+/// scope
+fn signature(secret_access_key: &str, scope: &str, string_to_sign: &str) -> String {
+    let key = scope.split('/').fold(
+        format!("AWS4{}", secret_access_key).into_bytes(),
+        |key, data| hmac_sha256(&key, data.as_bytes()),
+    );
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+        .as_ref()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SigV4 URI encoding: everything but unreserved characters is percent encoded
+/// (slashes are kept when encoding a path).
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `(YYYYMMDD, YYYYMMDD'T'HHMMSS'Z')` in UTC
+fn amz_dates(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let amz_date = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    (date, amz_date)
+}
+
+/// Parse the `YYYY-MM-DDTHH:MM:SSZ` timestamps returned by the metadata service.
+fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let field = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+    let days = days_from_civil(field(0..4)?, field(5..7)?, field(8..10)?);
+    let secs = days * 86400 + field(11..13)? * 3600 + field(14..16)? * 60 + field(17..19)?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))
+}
+
+// Calendar conversions from http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{amz_dates, canonical_request, payload_hash, signature, string_to_sign};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // Credentials, scope and time of the AWS SigV4 test suite
+    const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const SCOPE: &str = "20150830/us-east-1/service/aws4_request";
+
+    /// The canonical request, string to sign and signature of a request of the test suite
+    fn sign(request: reqwest::Request, headers: &[(&str, &str)]) -> (String, String, String) {
+        let (date, amz_date) = amz_dates(UNIX_EPOCH + Duration::from_secs(1_440_938_160));
+        assert_eq!(
+            (date.as_str(), amz_date.as_str()),
+            ("20150830", "20150830T123600Z")
+        );
+        let mut headers = headers
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect::<Vec<_>>();
+        headers.extend([
+            ("host", "example.amazonaws.com".to_string()),
+            ("x-amz-date", amz_date.clone()),
+        ]);
+        let canonical_request = canonical_request(&request, &headers, &payload_hash(&request));
+        let string_to_sign = string_to_sign(&amz_date, SCOPE, &canonical_request);
+        let signature = signature(SECRET_ACCESS_KEY, SCOPE, &string_to_sign);
+        (canonical_request, string_to_sign, signature)
+    }
+
+    #[test]
+    fn signs_the_post_vanilla_query_vector() {
+        let request = reqwest::Client::new()
+            .post("https://example.amazonaws.com/?Param1=value1")
+            .build()
+            .unwrap();
+        let (canonical_request, string_to_sign, signature) = sign(request, &[]);
+        assert_eq!(
+            canonical_request,
+            "POST\n/\nParam1=value1\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+             host;x-amz-date\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            string_to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n\
+             9d659678c1756bb3113e2ce898845a0a79dbbc57b740555917687f1b3340fbbd"
+        );
+        assert_eq!(
+            signature,
+            "28038455d6de14eafc1f9222cf5aa6f1a96197d7deb8263271d420d138af7f11"
+        );
+    }
+
+    #[test]
+    fn signs_the_post_x_www_form_urlencoded_vector() {
+        let request = reqwest::Client::new()
+            .post("https://example.amazonaws.com/")
+            .body("Param1=value1")
+            .build()
+            .unwrap();
+        let content_type = [("content-type", "application/x-www-form-urlencoded")];
+        let (canonical_request, string_to_sign, signature) = sign(request, &content_type);
+        assert_eq!(
+            canonical_request,
+            "POST\n/\n\ncontent-type:application/x-www-form-urlencoded\n\
+             host:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+             content-type;host;x-amz-date\n\
+             9095672bbd1f56dfc5b65f3e153adc8731a4a654192329106275f4c7b24d0b6e"
+        );
+        assert_eq!(
+            string_to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n\
+             42a5e5bb34198acb3e84da4f085bb7927f2bc277ca766e6d19c73c2154021281"
+        );
+        assert_eq!(
+            signature,
+            "ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn signs_a_post_with_a_body_and_a_query_string() {
+        // the test suite credentials on a delete by query, its parameters sorted
+        let request = reqwest::Client::new()
+            .post("https://example.amazonaws.com/logs/_delete_by_query?wait_for_completion=false&conflicts=proceed")
+            .body(r#"{"query":{"term":{"level":"debug"}}}"#)
+            .build()
+            .unwrap();
+        let (canonical_request, string_to_sign, signature) =
+            sign(request, &[("content-type", "application/json")]);
+        assert_eq!(
+            canonical_request,
+            "POST\n/logs/_delete_by_query\nconflicts=proceed&wait_for_completion=false\n\
+             content-type:application/json\nhost:example.amazonaws.com\n\
+             x-amz-date:20150830T123600Z\n\n\
+             content-type;host;x-amz-date\n\
+             7d3beb004c170ba6f96ca19a4454d7a8dce1d4cce52fec2a92edc7d229e5d003"
+        );
+        assert_eq!(
+            string_to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n\
+             b67b1ca66afa34c816d8d3f4f41d4526a0f1ef31fe2a9d9d0650f4fbe21ba353"
+        );
+        assert_eq!(
+            signature,
+            "10ab8f74f53a76a3c97fc7c7383f1f78fd8faab52ade33e5861913ae0c093a2e"
+        );
+    }
+}
@@ -0,0 +1,456 @@
+//! --chunk-window: a delete over a long time range split into consecutive windows, one smaller
+//! delete by query task per window, so that a run can be paused and resumed from the last
+//! completed window with --chunk-from.
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    events::{civil_from_days, timestamp},
+    read_query, Opt,
+};
+
+/// More windows than this are most likely a mistake in --chunk-window
+const MAX_WINDOWS: u64 = 10_000;
+
+/// A window of the chunked range, in milliseconds since the epoch, `to` excluded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Window {
+    /// The query restricted to the documents of the window
+    pub fn restrict(&self, field: &str, query: &serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "bool": {
+                "filter": [
+                    query,
+                    { "range": { field: {
+                        "gte": format_millis(self.from),
+                        "lt": format_millis(self.to),
+                        "format": "strict_date_optional_time"
+                    } } }
+                ]
+            }
+        })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} → {}",
+            format_millis(self.from),
+            format_millis(self.to)
+        )
+    }
+}
+
+/// eg: 2024-03-01T00:00:00.000Z
+pub fn format_millis(millis: u64) -> String {
+    timestamp(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// --chunk-window, eg: 7d, 12h, 1w (months and years have no fixed length)
+pub fn parse_window(value: &str) -> anyhow::Result<Duration> {
+    let window = parse_offset(value)
+        .filter(|millis| *millis > 0)
+        .ok_or_else(|| {
+            anyhow::anyhow!("expected a duration like 7d or 12h (units: w, d, h, m, s)")
+        })?;
+    Ok(Duration::from_millis(window as u64))
+}
+
+/// Milliseconds of an offset like 7d or 1d12h
+fn parse_offset(value: &str) -> Option<i64> {
+    let mut millis = 0i64;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let number = rest[..digits].parse::<i64>().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest.len()
+            - rest
+                .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+                .len();
+        let unit = match &rest[..unit_len] {
+            "w" => 7 * 86_400_000,
+            "d" => 86_400_000,
+            "h" => 3_600_000,
+            "m" => 60_000,
+            "s" => 1000,
+            _ => return None,
+        };
+        millis = millis.checked_add(number.checked_mul(unit)?)?;
+        rest = &rest[unit_len..];
+    }
+    Some(millis)
+}
+
+/// --chunk-from/--chunk-to, and the bounds of the range of the query: epoch milliseconds, an
+/// ISO 8601 date (eg: 2024-03-01 or 2024-03-01T12:00:00+02:00, UTC without offset), or now with
+/// offsets and rounding (eg: now-90d/d)
+pub fn parse_instant(value: &str) -> anyhow::Result<u64> {
+    let value = value.trim();
+    let millis = if let Some(math) = value.strip_prefix("now") {
+        parse_now(math)
+    } else if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        value.parse().ok()
+    } else {
+        parse_date(value)
+    };
+    match millis {
+        Some(millis) if millis >= 0 => Ok(millis as u64),
+        Some(_) => anyhow::bail!("{} is before 1970", value),
+        None => anyhow::bail!(
+            "expected a date like 2024-03-01 or 2024-03-01T12:00:00Z, epoch milliseconds or now-90d/d, got {}",
+            value
+        ),
+    }
+}
+
+/// `-90d/d` of now-90d/d
+fn parse_now(math: &str) -> Option<i64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let (offsets, rounding) = match math.split_once('/') {
+        Some((offsets, rounding)) => (offsets, Some(rounding)),
+        None => (math, None),
+    };
+    let mut millis = now;
+    let mut rest = offsets;
+    while !rest.is_empty() {
+        let sign = match rest.chars().next()? {
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        rest = &rest[1..];
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        millis += sign * parse_offset(&rest[..end])?;
+        rest = &rest[end..];
+    }
+    match rounding {
+        Some(unit) => {
+            let unit = parse_offset(&format!("1{}", unit))?;
+            Some(millis - millis.rem_euclid(unit))
+        }
+        None => Some(millis),
+    }
+}
+
+/// 2024-03-01, 2024-03-01T12:00, 2024-03-01T12:00:00.123Z, 2024-03-01 12:00:00+02:00
+fn parse_date(value: &str) -> Option<i64> {
+    let number = |text: &str| text.parse::<i64>().ok();
+    let (date, time) = match value.find(['T', ' ']) {
+        Some(at) => (&value[..at], &value[at + 1..]),
+        None => (value, ""),
+    };
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(parts.next()?)?,
+        number(parts.next()?)?,
+        number(parts.next()?)?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month as u32, day as u32);
+    // rejects the days past the end of the month, eg: 2023-02-30
+    if civil_from_days(days) != (year, month as u32, day as u32) {
+        return None;
+    }
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(at) => (&time[..at], &time[at..]),
+        None => (time, ""),
+    };
+    let offset_millis = match offset {
+        "" | "Z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let digits = offset[1..].replace(':', "");
+            if digits.len() != 4 {
+                return None;
+            }
+            sign * (number(&digits[..2])? * 3_600_000 + number(&digits[2..])? * 60_000)
+        }
+    };
+    let mut time_millis = 0;
+    if !time.is_empty() {
+        let mut parts = time.splitn(3, ':');
+        let hours = number(parts.next()?)?;
+        let minutes = number(parts.next()?)?;
+        let seconds = match parts.next() {
+            Some(seconds) => seconds.parse::<f64>().ok()?,
+            None => 0.0,
+        };
+        if hours > 23 || minutes > 59 || !(0.0..60.0).contains(&seconds) {
+            return None;
+        }
+        time_millis = hours * 3_600_000 + minutes * 60_000 + (seconds * 1000.0).round() as i64;
+    }
+    Some(days * 86_400_000 + time_millis - offset_millis)
+}
+
+/// Number of days since 1970-01-01 of a Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Field and bounds of the first range of the query, on `field` when given
+fn find_range<'a>(
+    query: &'a serde_json::Value,
+    field: Option<&str>,
+) -> Option<(&'a str, &'a serde_json::Map<String, serde_json::Value>)> {
+    match query {
+        serde_json::Value::Object(object) => {
+            let range = object.get("range").and_then(|range| range.as_object());
+            if let Some(range) = range {
+                for (name, bounds) in range {
+                    if let Some(bounds) = bounds.as_object() {
+                        if field.is_none_or(|field| field == name) {
+                            return Some((name, bounds));
+                        }
+                    }
+                }
+            }
+            object.values().find_map(|value| find_range(value, field))
+        }
+        serde_json::Value::Array(values) => {
+            values.iter().find_map(|value| find_range(value, field))
+        }
+        _ => None,
+    }
+}
+
+/// A bound of the range of the query, in milliseconds
+fn range_bound(
+    field: &str,
+    bounds: &serde_json::Map<String, serde_json::Value>,
+    names: &[&str],
+) -> anyhow::Result<Option<u64>> {
+    let bound = match names.iter().find_map(|name| bounds.get(*name)) {
+        Some(serde_json::Value::Null) | None => return Ok(None),
+        Some(bound) => bound,
+    };
+    let parsed = match bound {
+        serde_json::Value::Number(millis) => {
+            millis.as_u64().ok_or_else(|| anyhow::anyhow!("negative"))
+        }
+        serde_json::Value::String(date) => parse_instant(date),
+        _ => Err(anyhow::anyhow!("not a date")),
+    };
+    parsed.map(Some).map_err(|e| {
+        anyhow::anyhow!(
+            "Unable to chunk the range on {}: unsupported bound {} ({}), give --chunk-from and --chunk-to",
+            field,
+            bound,
+            e
+        )
+    })
+}
+
+/// Split the range to delete into the --chunk-window windows: the bounds come from
+/// --chunk-from/--chunk-to, or else from the range of the query on --chunk-field (or its first
+/// range), up to now without an upper bound
+pub fn resolve(opt: &mut Opt) -> anyhow::Result<()> {
+    let window = match opt.chunk_window {
+        Some(window) => window.as_millis() as u64,
+        None => return Ok(()),
+    };
+    let query = read_query(opt)?;
+    let range = find_range(&query, opt.chunk_field.as_deref());
+    let field = match (&opt.chunk_field, range) {
+        (Some(field), _) => field.clone(),
+        (None, Some((field, _))) => field.to_string(),
+        (None, None) => anyhow::bail!(
+            "No range in the query to chunk, give the date field with --chunk-field and the range with --chunk-from"
+        ),
+    };
+    let bound = |names: &[&str]| match range {
+        Some((field, bounds)) => range_bound(field, bounds, names),
+        None => Ok(None),
+    };
+    let from = match opt.chunk_from {
+        Some(from) => from,
+        None => bound(&["gte", "gt", "from"])?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "The range on {} has no lower bound, give where the chunks start with --chunk-from",
+                field
+            )
+        })?,
+    };
+    let to = match opt.chunk_to {
+        Some(to) => to,
+        // the last window includes an lte bound
+        None => match (bound(&["lt"])?, bound(&["lte", "to"])?) {
+            (Some(lt), _) => lt,
+            (None, Some(lte)) => lte + 1,
+            (None, None) => parse_instant("now")?,
+        },
+    };
+    if from >= to {
+        anyhow::bail!(
+            "Nothing to chunk: {} is not before {}",
+            format_millis(from),
+            format_millis(to)
+        );
+    }
+    let count = (to - from).div_ceil(window);
+    if count > MAX_WINDOWS {
+        anyhow::bail!(
+            "{} windows of {} ms between {} and {}, use a larger --chunk-window",
+            count,
+            window,
+            format_millis(from),
+            format_millis(to)
+        );
+    }
+    opt.chunk_field = Some(field);
+    opt.chunk_windows = (0..count)
+        .map(|i| Window {
+            from: from + i * window,
+            to: (from + (i + 1) * window).min(to),
+        })
+        .collect();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_range, format_millis, parse_instant, parse_window, resolve, Window};
+    use crate::Opt;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// The windows of the query, with --chunk-window and optionally --chunk-from/--chunk-to
+    fn windows(
+        query: serde_json::Value,
+        window: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let url = "http://localhost:9200".parse().unwrap();
+        let mut opt = Opt::default_for_job(url, "logs", &query);
+        opt.chunk_window = Some(parse_window(window).unwrap());
+        opt.chunk_from = from.map(|from| parse_instant(from).unwrap());
+        opt.chunk_to = to.map(|to| parse_instant(to).unwrap());
+        resolve(&mut opt).unwrap();
+        opt.chunk_windows
+            .iter()
+            .map(|window| (format_millis(window.from), format_millis(window.to)))
+            .collect()
+    }
+
+    fn window(from: &str, to: &str) -> (String, String) {
+        (from.to_string(), to.to_string())
+    }
+
+    #[test]
+    fn ends_with_a_partial_window() {
+        let query = json!({ "range": { "@timestamp": {
+            "gte": "2024-03-01", "lt": "2024-03-05T12:00:00Z"
+        } } });
+        assert_eq!(
+            windows(query, "2d", None, None),
+            [
+                window("2024-03-01T00:00:00.000Z", "2024-03-03T00:00:00.000Z"),
+                window("2024-03-03T00:00:00.000Z", "2024-03-05T00:00:00.000Z"),
+                window("2024-03-05T00:00:00.000Z", "2024-03-05T12:00:00.000Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_an_lte_bound_in_the_last_window() {
+        let query = json!({ "range": { "@timestamp": {
+            "gte": "2024-03-01", "lte": "2024-03-02"
+        } } });
+        assert_eq!(
+            windows(query, "1d", None, None),
+            [
+                window("2024-03-01T00:00:00.000Z", "2024-03-02T00:00:00.000Z"),
+                window("2024-03-02T00:00:00.000Z", "2024-03-02T00:00:00.001Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn starts_at_chunk_from_and_stops_before_chunk_to() {
+        let query = json!({ "range": { "@timestamp": {
+            "gte": "2024-01-01", "lt": "2024-12-31"
+        } } });
+        let windows = windows(query, "12h", Some("2024-03-01"), Some("2024-03-02"));
+        assert_eq!(
+            windows,
+            [
+                window("2024-03-01T00:00:00.000Z", "2024-03-01T12:00:00.000Z"),
+                window("2024-03-01T12:00:00.000Z", "2024-03-02T00:00:00.000Z"),
+            ]
+        );
+        // a window includes its start and excludes its end
+        let restricted = Window {
+            from: parse_instant(&windows[0].0).unwrap(),
+            to: parse_instant(&windows[0].1).unwrap(),
+        }
+        .restrict("@timestamp", &json!({ "match_all": {} }));
+        assert_eq!(
+            restricted["bool"]["filter"][1]["range"]["@timestamp"],
+            json!({
+                "gte": "2024-03-01T00:00:00.000Z",
+                "lt": "2024-03-01T12:00:00.000Z",
+                "format": "strict_date_optional_time"
+            })
+        );
+    }
+
+    #[test]
+    fn parses_the_window() {
+        let day = Duration::from_secs(86_400);
+        assert_eq!(parse_window("7d").unwrap(), 7 * day);
+        assert_eq!(parse_window("1w").unwrap(), 7 * day);
+        assert_eq!(parse_window("1d12h").unwrap(), day + day / 2);
+        assert_eq!(parse_window(" 90m ").unwrap(), Duration::from_secs(90 * 60));
+        // months and years have no fixed length
+        for invalid in ["", "0d", "7", "d", "1M", "1y", "7 d", "-1d"] {
+            assert!(parse_window(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn finds_the_range_in_a_nested_bool_query() {
+        let query = json!({ "bool": {
+            "must": [
+                { "term": { "level": "debug" } },
+                { "bool": { "filter": [
+                    { "range": { "@timestamp": { "gte": "2024-03-01", "lt": "2024-04-01" } } },
+                    { "range": { "ingested": { "lte": "now-30d" } } }
+                ] } }
+            ]
+        } });
+        let (field, bounds) = find_range(&query, None).unwrap();
+        assert_eq!(field, "@timestamp");
+        assert_eq!(bounds["lt"], "2024-04-01");
+        let (field, bounds) = find_range(&query, Some("ingested")).unwrap();
+        assert_eq!(field, "ingested");
+        assert_eq!(bounds["lte"], "now-30d");
+        assert!(find_range(&query, Some("updated")).is_none());
+        assert!(find_range(&json!({ "match_all": {} }), None).is_none());
+    }
+}
@@ -0,0 +1,88 @@
+//! Wall clock time of day, local or UTC, for the printed timestamps and the throttle schedule.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Broken down wall clock time
+#[derive(Clone, Copy, Debug)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Current time, in local time unless `utc`
+    #[cfg(unix)]
+    pub fn now(utc: bool) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as libc::time_t;
+        // SAFETY: localtime_r and gmtime_r only write to the tm struct they are given
+        let tm = unsafe {
+            let mut tm = std::mem::zeroed::<libc::tm>();
+            if utc {
+                libc::gmtime_r(&now, &mut tm);
+            } else {
+                libc::localtime_r(&now, &mut tm);
+            }
+            tm
+        };
+        DateTime {
+            year: i64::from(tm.tm_year) + 1900,
+            month: tm.tm_mon as u32 + 1,
+            day: tm.tm_mday as u32,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+            second: tm.tm_sec as u32,
+        }
+    }
+
+    /// Without a portable way to get the local offset, the time is always in UTC
+    #[cfg(not(unix))]
+    pub fn now(_utc: bool) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (year, month, day) = crate::events::civil_from_days((now / 86400) as i64);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (now / 3600 % 24) as u32,
+            minute: (now / 60 % 60) as u32,
+            second: (now % 60) as u32,
+        }
+    }
+
+    /// strftime like formatting, limited to %Y %m %d %H %M %S and %%
+    pub fn format(&self, format: &str) -> String {
+        let mut formatted = String::with_capacity(format.len() + 8);
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                formatted.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => formatted.push_str(&format!("{:04}", self.year)),
+                Some('m') => formatted.push_str(&format!("{:02}", self.month)),
+                Some('d') => formatted.push_str(&format!("{:02}", self.day)),
+                Some('H') => formatted.push_str(&format!("{:02}", self.hour)),
+                Some('M') => formatted.push_str(&format!("{:02}", self.minute)),
+                Some('S') => formatted.push_str(&format!("{:02}", self.second)),
+                Some('%') => formatted.push('%'),
+                Some(other) => {
+                    formatted.push('%');
+                    formatted.push(other);
+                }
+                None => formatted.push('%'),
+            }
+        }
+        formatted
+    }
+}
@@ -0,0 +1,84 @@
+//! --cloud-id: the Elastic Cloud deployment id shown in the console, decoded into the url of its
+//! Elasticsearch endpoint.
+
+use url::Url;
+
+/// Url of the Elasticsearch endpoint of a Cloud ID, eg: `name:base64(host$es_uuid$kibana_uuid)`
+/// gives `https://<es_uuid>.<host>:443`
+pub fn cloud_url(cloud_id: &str) -> anyhow::Result<Url> {
+    let malformed = |reason: &str| {
+        anyhow::anyhow!(
+            "Malformed Cloud ID '{}': {}, copy it again from the Elastic Cloud console",
+            cloud_id,
+            reason
+        )
+    };
+    // the deployment name before the colon is optional
+    let encoded = cloud_id.rsplit(':').next().unwrap_or_default();
+    let decoded = base64::decode(encoded.trim())
+        .map_err(|e| malformed(&format!("invalid base64 ({})", e)))?;
+    let decoded = String::from_utf8(decoded).map_err(|_| malformed("not UTF-8"))?;
+    let mut parts = decoded.trim_end_matches('\n').split('$');
+    let host = parts.next().unwrap_or_default();
+    let es_uuid = parts.next().unwrap_or_default();
+    if host.is_empty() || es_uuid.is_empty() {
+        return Err(malformed("expected host$es_uuid$kibana_uuid"));
+    }
+    // the host may carry the port, otherwise 443
+    let (host, port) = match host.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host, port),
+        _ => (host, "443"),
+    };
+    Url::parse(&format!("https://{}.{}:{}", es_uuid, host, port))
+        .map_err(|e| malformed(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cloud_url;
+
+    /// eu-west-1.aws.found.io$0123abcd$4567ef
+    const ENCODED: &str = "ZXUtd2VzdC0xLmF3cy5mb3VuZC5pbyQwMTIzYWJjZCQ0NTY3ZWY=";
+
+    #[test]
+    fn decodes_the_cloud_id_with_or_without_its_name() {
+        for cloud_id in [format!("production:{}", ENCODED), ENCODED.to_string()] {
+            assert_eq!(
+                cloud_url(&cloud_id).unwrap().as_str(),
+                "https://0123abcd.eu-west-1.aws.found.io/"
+            );
+        }
+        // the name may contain colons too
+        let cloud_id = format!("eu:logs:{}", ENCODED);
+        assert_eq!(
+            cloud_url(&cloud_id).unwrap().host_str(),
+            Some("0123abcd.eu-west-1.aws.found.io")
+        );
+    }
+
+    #[test]
+    fn keeps_the_port_of_the_host() {
+        // us-central1.gcp.cloud.es.io:9243$0123abcd$4567ef
+        let cloud_id = "logs:dXMtY2VudHJhbDEuZ2NwLmNsb3VkLmVzLmlvOjkyNDMkMDEyM2FiY2QkNDU2N2Vm";
+        assert_eq!(
+            cloud_url(cloud_id).unwrap().as_str(),
+            "https://0123abcd.us-central1.gcp.cloud.es.io:9243/"
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_cloud_id() {
+        let error = |cloud_id: &str| cloud_url(cloud_id).unwrap_err().to_string();
+        assert!(error("logs:not base64!").contains("invalid base64"));
+        // eu-west-1.aws.found.io$$4567ef
+        assert!(error("logs:ZXUtd2VzdC0xLmF3cy5mb3VuZC5pbyQkNDU2N2Vm")
+            .contains("expected host$es_uuid$kibana_uuid"));
+        // eu-west-1.aws.found.io
+        assert_eq!(
+            error("logs:ZXUtd2VzdC0xLmF3cy5mb3VuZC5pbw=="),
+            "Malformed Cloud ID 'logs:ZXUtd2VzdC0xLmF3cy5mb3VuZC5pbw==': expected \
+             host$es_uuid$kibana_uuid, copy it again from the Elastic Cloud console"
+        );
+        assert!(error("").contains("expected host$es_uuid$kibana_uuid"));
+    }
+}
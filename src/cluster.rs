@@ -0,0 +1,411 @@
+//! The version of the cluster, read from its root endpoint or given with --assume-version, to
+//! only send the delete by query parameters it supports. Its name guards against running on the
+//! wrong cluster. Before anything is submitted, its root endpoint is read with a short timeout:
+//! a wrong --url or a cluster down fails fast, with a hint at what to fix.
+
+use std::{fmt, time::Duration};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    api_url, display_url, error_for_status, json_body, snippet, ErrorResponse, EsClient, Opt,
+    RESPONSE_SNIPPET_MAX_CHARS,
+};
+
+/// Timeout of the connection pre-flight, the root endpoint answers right away
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// --flavor: the product of the cluster
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flavor {
+    Elasticsearch,
+    OpenSearch,
+    /// Read from the root endpoint
+    Auto,
+}
+
+impl std::str::FromStr for Flavor {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "elasticsearch" => Ok(Flavor::Elasticsearch),
+            "opensearch" => Ok(Flavor::OpenSearch),
+            "auto" => Ok(Flavor::Auto),
+            _ => anyhow::bail!("expected elasticsearch, opensearch or auto, got {}", value),
+        }
+    }
+}
+
+/// Major and minor version, eg: 7.17
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+/// `max_docs`, `size` in the body before
+pub const MAX_DOCS: Version = Version::new(7, 3);
+/// `slices=auto`
+pub const SLICES_AUTO: Version = Version::new(6, 1);
+/// The X-Elastic-Product response header
+pub const PRODUCT_HEADER: Version = Version::new(7, 14);
+/// `sort` in the body is rejected from this version on
+pub const SORT_REJECTED: Version = Version::new(8, 0);
+/// OpenSearch forked from Elasticsearch 7.10
+const OPENSEARCH_FORK: Version = Version::new(7, 10);
+
+impl Version {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Version { major, minor }
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = anyhow::Error;
+
+    /// eg: 7, 7.17, 7.17.3 or 8.0.0-SNAPSHOT
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let mut numbers = value.split(['.', '-']);
+        let mut number = || numbers.next().map(str::parse::<u32>).transpose();
+        match (number(), number()) {
+            (Ok(Some(major)), Ok(minor)) => Ok(Version::new(major, minor.unwrap_or(0))),
+            _ => anyhow::bail!("expected a version like 7.17, got {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// What the cluster told about itself
+#[derive(Clone, Debug)]
+pub struct ClusterInfo {
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    /// Full version number, eg: 7.17.3
+    pub number: String,
+    pub version: Version,
+    /// opensearch for OpenSearch, absent for Elasticsearch
+    pub distribution: Option<String>,
+}
+
+impl ClusterInfo {
+    /// Cluster of the --assume-version
+    pub fn assumed(version: Version, flavor: Flavor) -> Self {
+        ClusterInfo {
+            name: None,
+            uuid: None,
+            number: version.to_string(),
+            version,
+            distribution: (flavor == Flavor::OpenSearch).then(|| "opensearch".to_string()),
+        }
+    }
+
+    pub fn is_opensearch(&self) -> bool {
+        self.distribution.as_deref() == Some("opensearch")
+    }
+
+    /// The Elasticsearch version whose API the cluster has
+    pub fn api_version(&self) -> Version {
+        if self.is_opensearch() {
+            OPENSEARCH_FORK
+        } else {
+            self.version
+        }
+    }
+
+    /// Whether the cluster has a feature added in Elasticsearch `version`
+    pub fn supports(&self, version: Version) -> bool {
+        self.api_version() >= version
+    }
+}
+
+impl fmt::Display for ClusterInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{}, ", name)?;
+        }
+        let product = if self.is_opensearch() {
+            "OpenSearch"
+        } else {
+            "Elasticsearch"
+        };
+        write!(f, "{} {}", product, self.number)
+    }
+}
+
+/// Health of the cluster, from the best to the worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Green,
+    Yellow,
+    Red,
+}
+
+impl std::str::FromStr for Health {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "green" => Ok(Health::Green),
+            "yellow" => Ok(Health::Yellow),
+            "red" => Ok(Health::Red),
+            _ => anyhow::bail!("expected green, yellow or red, got {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Green => "green",
+            Health::Yellow => "yellow",
+            Health::Red => "red",
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct HealthResponse {
+    cluster_name: Option<String>,
+    status: Health,
+}
+
+async fn get_health(opt: &Opt, client: &EsClient) -> anyhow::Result<HealthResponse> {
+    let mut url = api_url(&opt.url, "_cluster/health")?;
+    url.query_pairs_mut().append_pair("timeout", "5s");
+    let response = error_for_status(client.send(client.get(url)).await?).await?;
+    json_body::<HealthResponse>(response).await
+}
+
+/// Current health of the cluster
+pub async fn health(opt: &Opt, client: &EsClient) -> anyhow::Result<Health> {
+    Ok(get_health(opt, client).await?.status)
+}
+
+#[derive(Deserialize)]
+struct RootResponse {
+    cluster_name: Option<String>,
+    cluster_uuid: Option<String>,
+    version: RootVersion,
+}
+
+#[derive(Deserialize)]
+struct RootVersion {
+    number: String,
+    distribution: Option<String>,
+}
+
+impl RootResponse {
+    fn cluster_info(self) -> anyhow::Result<ClusterInfo> {
+        Ok(ClusterInfo {
+            name: self.cluster_name,
+            uuid: self.cluster_uuid,
+            version: self.version.number.parse()?,
+            number: self.version.number,
+            distribution: self.version.distribution,
+        })
+    }
+}
+
+/// Read the version from the root endpoint
+pub async fn detect(opt: &Opt, client: &EsClient) -> anyhow::Result<ClusterInfo> {
+    let url = api_url(&opt.url, "")?;
+    let response = error_for_status(client.send(client.get(url)).await?).await?;
+    json_body::<RootResponse>(response).await?.cluster_info()
+}
+
+/// Connection pre-flight (unless --skip-preflight): read the root endpoint with a short timeout
+/// to check the connection and the credentials, the error tells what is likely wrong
+pub async fn ping(opt: &Opt, client: &EsClient) -> anyhow::Result<ClusterInfo> {
+    let url = api_url(&opt.url, "")?;
+    let timeout = PING_TIMEOUT.max(client.connect_timeout);
+    let response = client
+        .send(client.get_with_timeout(url.clone(), Some(timeout)))
+        .await
+        .map_err(|e| connection_failure(&url, &e))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| connection_failure(&url, &e.into()))?;
+    if status.as_u16() == 401 || status.as_u16() == 403 {
+        anyhow::bail!(
+            "the cluster rejected the credentials (HTTP {})\n  hint: give them with --user/--password, --api-key, --bearer-token or --aws-sigv4, or check their privileges (403)",
+            status
+        );
+    }
+    if let Ok(error) = serde_json::from_str::<ErrorResponse>(&body) {
+        let hint = if status.is_server_error() {
+            "the cluster may be down or still starting"
+        } else {
+            "check the path of --url"
+        };
+        anyhow::bail!(
+            "the cluster answered HTTP {}: {}\n  hint: {}",
+            status,
+            error.error,
+            hint
+        );
+    }
+    let root = serde_json::from_str::<RootResponse>(&body)
+        .ok()
+        .filter(|_| status.is_success())
+        .and_then(|root| root.cluster_info().ok());
+    root.ok_or_else(|| {
+        anyhow::anyhow!(
+            "{} does not look like Elasticsearch (HTTP {}): {}\n  hint: check the scheme, host, port and path of --url, or use --skip-preflight if a proxy blocks the root endpoint",
+            display_url(&url),
+            status,
+            snippet(&body, RESPONSE_SNIPPET_MAX_CHARS)
+        )
+    })
+}
+
+/// The failure to send the request with a hint at its likely cause
+fn connection_failure(url: &url::Url, error: &anyhow::Error) -> anyhow::Error {
+    let refused = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+    });
+    let causes = error
+        .chain()
+        .map(|cause| cause.to_string().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(": ");
+    let host = url.host_str().unwrap_or_default();
+    let (problem, hint) = if causes.contains("dns error") || causes.contains("lookup address") {
+        (
+            format!("the host name {} cannot be resolved", host),
+            "check the host of --url",
+        )
+    } else if refused || causes.contains("connection refused") {
+        (
+            format!(
+                "{}:{} refused the connection",
+                host,
+                url.port_or_known_default().unwrap_or_default()
+            ),
+            "check the port of --url and that the cluster is running",
+        )
+    } else if causes.contains("wrong version number") {
+        (
+            format!("TLS handshake failed: {:#}", error),
+            "the cluster may not use TLS, try an http:// --url",
+        )
+    } else if ["certificate", "tls", "ssl", "handshake"]
+        .iter()
+        .any(|word| causes.contains(word))
+    {
+        (
+            format!("TLS error: {:#}", error),
+            "give the CA certificate of the cluster with --cacert, or skip the verification with --insecure",
+        )
+    } else if causes.contains("timed out") {
+        (
+            format!("{:#}", error),
+            "check the host and port of --url, a firewall may drop the connection",
+        )
+    } else {
+        (format!("{:#}", error), "check --url")
+    };
+    anyhow::anyhow!("{}\n  hint: {}", problem, hint)
+}
+
+/// A cluster name with `*` wildcards, or a regex between slashes, eg: prod-* or /staging-\d+/
+#[derive(Clone, Debug)]
+pub struct ClusterPattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl ClusterPattern {
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl std::str::FromStr for ClusterPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let regex = match value
+            .strip_prefix('/')
+            .and_then(|regex| regex.strip_suffix('/'))
+        {
+            Some(regex) => format!("^(?:{})$", regex),
+            None => format!(
+                "^{}$",
+                value
+                    .split('*')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".*")
+            ),
+        };
+        Ok(ClusterPattern {
+            pattern: value.to_string(),
+            regex: Regex::new(&regex)
+                .map_err(|e| anyhow::anyhow!("invalid cluster pattern {}: {}", value, e))?,
+        })
+    }
+}
+
+impl fmt::Display for ClusterPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// --expect-cluster and the protected_clusters of the configuration file: the reason to refuse
+/// running on the cluster, if any. The name comes from the root endpoint already read, else from
+/// the cluster health; a cluster whose name cannot be read is refused.
+pub async fn guard(opt: &Opt, client: &mut EsClient) -> Option<String> {
+    if opt.expect_cluster.is_none() && opt.protected_clusters.is_empty() {
+        return None;
+    }
+    let name = match client
+        .cluster
+        .as_ref()
+        .and_then(|cluster| cluster.name.clone())
+    {
+        Some(name) => name,
+        None => match get_health(opt, client).await {
+            Ok(HealthResponse {
+                cluster_name: Some(name),
+                ..
+            }) => {
+                if let Some(cluster) = &mut client.cluster {
+                    cluster.name = Some(name.clone());
+                }
+                name
+            }
+            Ok(_) => return Some("the cluster does not tell its name".to_string()),
+            Err(e) => return Some(format!("unable to read the cluster name: {}", e)),
+        },
+    };
+    if let Some(expected) = opt
+        .expect_cluster
+        .as_ref()
+        .filter(|expected| !expected.matches(&name))
+    {
+        return Some(format!(
+            "the cluster is {}, not the expected {}",
+            name, expected
+        ));
+    }
+    match opt.protected_clusters.iter().find(|protected| protected.matches(&name)) {
+        Some(protected) if !opt.allow_protected_cluster => Some(format!(
+            "the cluster {} is protected ({} in the configuration file), use --allow-protected-cluster to run on it anyway",
+            name, protected
+        )),
+        _ => None,
+    }
+}
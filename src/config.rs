@@ -0,0 +1,203 @@
+//! --profile: the connection settings of a cluster, and its usual index and throttle, read from a
+//! named profile of the configuration file, eg:
+//!
+//! ```toml
+//! protected_clusters = ["prod-*"]
+//!
+//! [profiles.logs-prod]
+//! url = "https://logs.example.com:9200"
+//! user = "purger"
+//! password = "env:LOGS_PROD_PASSWORD"
+//! cacert = "/etc/ssl/logs-ca.pem"
+//! index = "logs-*"
+//! requests_per_second = 500
+//! expect_cluster = "logs-prod"
+//! ```
+//!
+//! The command line options and their environment variables take precedence over the profile.
+//! The protected clusters (names with `*` wildcards, or /regex/) are refused without
+//! --allow-protected-cluster, whatever the profile.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::{has_credentials, parse_requests_per_second, Opt};
+
+#[derive(Deserialize)]
+struct Config {
+    #[serde(default)]
+    protected_clusters: Vec<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct Profile {
+    url: Option<Url>,
+    cloud_id: Option<String>,
+    api_key: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    bearer_token: Option<String>,
+    bearer_token_file: Option<PathBuf>,
+    aws_sigv4: Option<String>,
+    cacert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    client_cert_password: Option<String>,
+    insecure: Option<bool>,
+    /// Default index pattern
+    index: Option<String>,
+    /// Default throttle, -1 for unlimited
+    requests_per_second: Option<f64>,
+    /// Default --expect-cluster
+    expect_cluster: Option<String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// ~/.config/es-delete-by-query/config.toml, or under $XDG_CONFIG_HOME
+fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("es-delete-by-query").join("config.toml"))
+}
+
+/// Read the protected clusters of the configuration file, and fill the options not given on the
+/// command line from its --profile. Without --profile nor --config, the file is optional.
+pub fn apply_config(opt: &mut Opt) -> anyhow::Result<()> {
+    let path = match (opt.config.clone().or_else(default_path), &opt.profile) {
+        (Some(path), None) if opt.config.is_none() && !path.exists() => return Ok(()),
+        (Some(path), _) => path,
+        (None, None) => return Ok(()),
+        (None, Some(name)) => {
+            anyhow::bail!("No configuration file to read the profile {} from", name)
+        }
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        anyhow::anyhow!(
+            "Unable to read the configuration file {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let mut config = toml::from_str::<Config>(&text)
+        .map_err(|e| anyhow::anyhow!("Invalid configuration file {}: {}", path.display(), e))?;
+    for key in config.unknown.keys() {
+        eprintln!("Warning: unknown key '{}' in {}", key, path.display());
+    }
+    for (profile_name, profile) in &config.profiles {
+        for key in profile.unknown.keys() {
+            eprintln!(
+                "Warning: unknown key '{}' in profile {} of {}",
+                key,
+                profile_name,
+                path.display()
+            );
+        }
+    }
+    opt.protected_clusters = config
+        .protected_clusters
+        .iter()
+        .map(|pattern| pattern.parse())
+        .collect::<anyhow::Result<_>>()
+        .map_err(|e| anyhow::anyhow!("Invalid protected_clusters in {}: {}", path.display(), e))?;
+    let name = match &opt.profile {
+        Some(name) => name.clone(),
+        None => return Ok(()),
+    };
+    let profile = match config.profiles.remove(&name) {
+        Some(profile) => profile,
+        None => anyhow::bail!(
+            "No profile {} in {} (available: {})",
+            name,
+            path.display(),
+            config
+                .profiles
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    // the cluster: --url and --cloud-id replace both
+    if opt.urls.is_empty() && opt.cloud_id.is_none() {
+        if let Some(url) = profile.url {
+            opt.url = url;
+        }
+        opt.cloud_id = profile.cloud_id;
+    }
+    // the credentials are taken as a whole, unless some were given
+    if !has_credentials(opt) {
+        opt.api_key = profile.api_key.map(|key| secret(&key)).transpose()?;
+        opt.user = profile.user;
+        opt.password = profile
+            .password
+            .map(|password| secret(&password))
+            .transpose()?;
+        opt.bearer_token = profile
+            .bearer_token
+            .map(|token| secret(&token))
+            .transpose()?;
+        opt.bearer_token_file = profile.bearer_token_file;
+        opt.aws_sigv4 = profile.aws_sigv4;
+    }
+    if opt.cacert.is_none() {
+        opt.cacert = profile.cacert;
+    }
+    if opt.client_cert.is_none() {
+        opt.client_cert = profile.client_cert;
+        opt.client_key = profile.client_key;
+        opt.client_cert_password = profile
+            .client_cert_password
+            .map(|password| secret(&password))
+            .transpose()?;
+    }
+    opt.insecure |= profile.insecure.unwrap_or(false);
+    if opt.indices.is_empty() {
+        opt.indices.extend(profile.index);
+    }
+    if opt.requests_per_second.is_none() {
+        opt.requests_per_second = profile
+            .requests_per_second
+            .map(|requests_per_second| {
+                parse_requests_per_second(&requests_per_second.to_string()).map_err(|e| {
+                    anyhow::anyhow!("Invalid requests_per_second in profile {}: {}", name, e)
+                })
+            })
+            .transpose()?;
+    }
+    if opt.expect_cluster.is_none() {
+        opt.expect_cluster = profile
+            .expect_cluster
+            .map(|pattern| {
+                pattern.parse().map_err(|e| {
+                    anyhow::anyhow!("Invalid expect_cluster in profile {}: {}", name, e)
+                })
+            })
+            .transpose()?;
+    }
+    Ok(())
+}
+
+/// The value, or the content of the environment variable it names with `env:VAR_NAME`
+fn secret(value: &str) -> anyhow::Result<String> {
+    match value.strip_prefix("env:") {
+        Some(variable) => std::env::var(variable).map_err(|_| {
+            anyhow::anyhow!(
+                "The environment variable {} referenced by the profile is not set",
+                variable
+            )
+        }),
+        None => Ok(value.to_string()),
+    }
+}
@@ -0,0 +1,84 @@
+//! --dump-task-responses: the raw bodies of the submissions and of the task polls written to a
+//! directory before they are decoded, to diagnose a response the tool could not make sense of
+//! without asking for a curl of `_tasks/{id}` after the fact.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use crate::events::timestamp;
+
+pub struct ResponseDump {
+    dir: PathBuf,
+    /// 0 keeps every file
+    max_files: usize,
+    /// Files written by this run, oldest first
+    written: Mutex<VecDeque<PathBuf>>,
+    sequence: AtomicU64,
+    /// Whether the last write failed, to warn once per series of failures
+    failing: AtomicBool,
+}
+
+impl ResponseDump {
+    /// Creates the directory, so that a wrong path is reported before anything is deleted
+    pub fn new(dir: PathBuf, max_files: usize) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Unable to create the --dump-task-responses directory {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+        Ok(ResponseDump {
+            dir,
+            max_files,
+            written: Mutex::new(VecDeque::new()),
+            sequence: AtomicU64::new(0),
+            failing: AtomicBool::new(false),
+        })
+    }
+
+    /// Write a body, pretty printed when it is JSON, eg: 20240503T010203.123Z-000042-task-node_1.json.
+    /// Beyond --dump-max-files, the oldest file of the run is removed. Errors are only reported:
+    /// losing a dump must not stop the deletion.
+    pub fn write(&self, kind: &str, body: &str) {
+        let name = format!(
+            "{}-{:06}-{}.json",
+            timestamp(SystemTime::now()).replace([':', '-'], ""),
+            self.sequence.fetch_add(1, Ordering::SeqCst) + 1,
+            kind.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_")
+        );
+        let path = self.dir.join(name);
+        let contents = serde_json::from_str::<serde_json::Value>(body)
+            .and_then(|value| serde_json::to_string_pretty(&value))
+            .unwrap_or_else(|_| body.to_string());
+        if let Err(e) = fs::write(&path, contents) {
+            if !self.failing.swap(true, Ordering::SeqCst) {
+                eprintln!(
+                    "Warning: unable to dump a response to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+            return;
+        }
+        self.failing.store(false, Ordering::SeqCst);
+        let mut written = self
+            .written
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        written.push_back(path);
+        while self.max_files > 0 && written.len() > self.max_files {
+            if let Some(oldest) = written.pop_front() {
+                let _ = fs::remove_file(oldest);
+            }
+        }
+    }
+}
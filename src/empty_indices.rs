@@ -0,0 +1,85 @@
+//! --delete-empty-indices: the target indices left without any document by the delete removed,
+//! to not keep hundreds of empty indices in the cluster state.
+
+use crate::{
+    error_for_status,
+    preflight::{cat_indices, TargetIndex},
+    segments_url, EsClient, Opt,
+};
+
+/// Delete the target indices the delete left empty: only the ones matched by the index
+/// expression itself (not through an alias), never the hidden, system or data stream ones.
+/// Returns the deleted indices and the failures.
+pub async fn delete(
+    opt: &Opt,
+    targets: &[TargetIndex],
+    client: &EsClient,
+) -> (Vec<String>, Vec<String>) {
+    let candidates = targets
+        .iter()
+        .filter(|target| target.via_alias.is_none() && !target.protected)
+        .map(|target| target.name.as_str())
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    let expression = candidates.join(",");
+    // the deleted documents are counted until a refresh
+    if let Err(e) = refresh(opt, client, &expression).await {
+        eprintln!(
+            "Warning: unable to refresh the target indices, no empty index deleted: {}",
+            e
+        );
+        return (Vec::new(), Vec::new());
+    }
+    let empty = match cat_indices(opt, client, &expression).await {
+        Ok(indices) => indices
+            .into_iter()
+            .filter(|index| {
+                index.docs_count.as_deref() == Some("0")
+                    && candidates.contains(&index.index.as_str())
+            })
+            .map(|index| index.index)
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!(
+                "Warning: unable to count the documents of the target indices, no empty index deleted: {}",
+                e
+            );
+            return (Vec::new(), Vec::new());
+        }
+    };
+    if empty.is_empty() {
+        eprintln!("No target index left empty");
+        return (Vec::new(), Vec::new());
+    }
+    eprintln!(
+        "Deleting the {} indices left empty: {}",
+        empty.len(),
+        empty.join(", ")
+    );
+    let mut deleted = Vec::new();
+    let mut failures = Vec::new();
+    for index in empty {
+        match delete_index(opt, client, &index).await {
+            Ok(()) => deleted.push(index),
+            Err(e) => {
+                eprintln!("Unable to delete the empty index {}: {}", index, e);
+                failures.push(format!("{}: {}", index, e));
+            }
+        }
+    }
+    (deleted, failures)
+}
+
+async fn delete_index(opt: &Opt, client: &EsClient, index: &str) -> anyhow::Result<()> {
+    let url = segments_url(&opt.url, [index])?;
+    error_for_status(client.send(client.delete(url)).await?).await?;
+    Ok(())
+}
+
+async fn refresh(opt: &Opt, client: &EsClient, expression: &str) -> anyhow::Result<()> {
+    let url = segments_url(&opt.url, [expression, "_refresh"])?;
+    error_for_status(client.send(client.post(url)).await?).await?;
+    Ok(())
+}
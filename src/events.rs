@@ -0,0 +1,131 @@
+//! --progress jsonl: progress events streamed on stdout, one JSON object per line.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{FailureReport, Opt, RunResult, TaskId, TaskStatus};
+
+/// How the progress of a run is reported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Animated progress bar on stderr
+    Bar,
+    /// JSON Lines events on stdout, the human messages go to stderr
+    Jsonl,
+}
+
+impl std::str::FromStr for ProgressFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "bar" => Ok(ProgressFormat::Bar),
+            "jsonl" => Ok(ProgressFormat::Jsonl),
+            _ => anyhow::bail!("expected bar or jsonl, got {}", value),
+        }
+    }
+}
+
+/// An event of the --progress jsonl stream, eg:
+/// `{"ts":"2024-03-01T12:00:00.000Z","event":"progress","task":"node:123","total":10,...}`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A delete by query task was submitted
+    Submitted { task: &'a TaskId, index: &'a str },
+    /// Status of a running task, at each poll
+    Progress(TaskProgress<'a>),
+    /// The task failed, it is submitted again after `delay_secs`
+    Retrying {
+        task: &'a TaskId,
+        retry: u32,
+        delay_secs: f64,
+        failures: &'a [FailureReport],
+    },
+    /// The task completed without failures
+    Completed(TaskProgress<'a>),
+    /// The task stopped on a cancellation
+    Cancelled(TaskProgress<'a>),
+    /// Last event of the stream: the --output json result
+    Result(&'a RunResult),
+}
+
+#[derive(Serialize)]
+pub struct TaskProgress<'a> {
+    task: &'a TaskId,
+    total: u64,
+    deleted: u64,
+    conflicts: u64,
+    batches: u64,
+}
+
+impl<'a> TaskProgress<'a> {
+    pub fn new(task: &'a TaskId, status: &TaskStatus) -> Self {
+        TaskProgress {
+            task,
+            total: status.total().max(0) as u64,
+            deleted: status.processed().max(0) as u64,
+            conflicts: status.version_conflicts.max(0) as u64,
+            batches: status.batches.max(0) as u64,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TimestampedEvent<'a> {
+    ts: String,
+    /// Url of the cluster, when the delete runs on several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<&'a str>,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Print the event on stdout with --progress jsonl, does nothing otherwise
+pub fn emit(opt: &Opt, event: Event<'_>) {
+    if opt.progress != ProgressFormat::Jsonl {
+        return;
+    }
+    let event = TimestampedEvent {
+        ts: timestamp(SystemTime::now()),
+        cluster: opt.cluster_label.as_deref(),
+        event,
+    };
+    match serde_json::to_string(&event) {
+        Ok(line) => println!("{}", line),
+        Err(e) => eprintln!("Unable to serialize the progress event: {}", e),
+    }
+}
+
+/// RFC 3339 UTC timestamp with milliseconds, eg: 2024-03-01T12:00:00.000Z
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a number of days since 1970-01-01 (Howard Hinnant's algorithm)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
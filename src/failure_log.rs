@@ -0,0 +1,87 @@
+//! --failure-log: every failure of the failed attempts appended to a file as JSON lines, to keep
+//! them once the run output scrolled away.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+use crate::{events::timestamp, Failure, TaskId};
+
+pub struct FailureLog {
+    path: PathBuf,
+    /// Opened on the first write, and again after a write error
+    file: Mutex<Option<File>>,
+}
+
+/// A line of the failure log
+#[derive(Serialize)]
+struct FailureRecord<'a> {
+    ts: String,
+    attempt: u32,
+    task: &'a TaskId,
+    #[serde(flatten)]
+    failure: &'a Failure,
+}
+
+impl FailureLog {
+    pub fn new(path: PathBuf) -> Self {
+        FailureLog {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Append the failures of an attempt, returns how many were written. Errors are only
+    /// reported: losing the log must not stop the deletion.
+    pub fn append(&self, task: &TaskId, attempt: u32, failures: &[Failure]) -> u64 {
+        let ts = timestamp(SystemTime::now());
+        let mut lines = String::new();
+        let mut count = 0;
+        for failure in failures {
+            let record = FailureRecord {
+                ts: ts.clone(),
+                attempt,
+                task,
+                failure,
+            };
+            match serde_json::to_string(&record) {
+                Ok(line) => {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                    count += 1;
+                }
+                Err(e) => eprintln!("Warning: unable to serialize a failure: {}", e),
+            }
+        }
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let result = match &mut *file {
+            Some(file) => file.write_all(lines.as_bytes()),
+            None => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|opened| file.insert(opened).write_all(lines.as_bytes())),
+        };
+        match result {
+            Ok(()) => count,
+            Err(e) => {
+                eprintln!(
+                    "Warning: unable to write the failures to {}: {}",
+                    self.path.display(),
+                    e
+                );
+                *file = None;
+                0
+            }
+        }
+    }
+}
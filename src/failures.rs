@@ -0,0 +1,252 @@
+//! Classification of the delete by query failures: transient failures are worth a retry, the
+//! others would fail again the same way. The failures of a task are shown grouped by index and
+//! type, the most frequent first.
+
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::{snippet, Failure, Opt};
+
+/// Characters of the reason shown as the sample of a group of failures
+const SAMPLE_MAX_CHARS: usize = 200;
+
+/// Failure types caused by a temporary state of the cluster
+pub const RETRYABLE_FAILURE_TYPES: &[&str] = &[
+    "es_rejected_execution_exception",
+    "search_context_missing_exception",
+    "unavailable_shards_exception",
+    "circuit_breaking_exception",
+    "no_shard_available_action_exception",
+    "node_not_connected_exception",
+    "node_disconnected_exception",
+    "receive_timeout_transport_exception",
+];
+
+/// Retryable failure types of an overloaded cluster, that a slower delete avoids
+pub const LOAD_FAILURE_TYPES: &[&str] = &[
+    "es_rejected_execution_exception",
+    "circuit_breaking_exception",
+];
+
+/// Failure type of the documents changed during the delete, with --abort-on-conflict
+pub const VERSION_CONFLICT_TYPE: &str = "version_conflict_engine_exception";
+
+/// Failure type of the writes to an index with a block, eg: read-only past the flood-stage disk
+/// watermark, or frozen
+pub const BLOCK_FAILURE_TYPE: &str = "cluster_block_exception";
+
+/// The block set when a node exceeds the flood-stage disk watermark, the one --clear-read-only-block
+/// removes
+pub const FLOOD_STAGE_BLOCK: &str = "index.blocks.read_only_allow_delete";
+
+/// Blocks of an index that reject the deletions, as worded in the reason of the failures
+/// (eg: index [logs] blocked by: [FORBIDDEN/12/index read-only / allow delete (api)])
+const BLOCK_REASONS: &[(&str, &str)] = &[
+    ("read-only / allow delete", FLOOD_STAGE_BLOCK),
+    ("read-only-allow-delete", FLOOD_STAGE_BLOCK),
+    ("index read-only", "index.blocks.read_only"),
+    ("index write", "index.blocks.write"),
+];
+
+/// A block of an index that rejected the deletions
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexBlock {
+    pub index: String,
+    /// The setting of the block, eg: index.blocks.read_only_allow_delete
+    pub block: &'static str,
+}
+
+impl IndexBlock {
+    /// The block of a failure (of a task or of one of its documents), None for another failure
+    pub fn of(index: Option<&str>, r#type: &str, reason: &str) -> Option<Self> {
+        if r#type != BLOCK_FAILURE_TYPE {
+            return None;
+        }
+        let index = index
+            .map(str::to_string)
+            .or_else(|| {
+                let start = reason.find("index [")? + "index [".len();
+                let end = start + reason[start..].find(']')?;
+                Some(reason[start..end].to_string())
+            })
+            .unwrap_or_else(|| "?".to_string());
+        let block = BLOCK_REASONS
+            .iter()
+            .find(|(wording, _)| reason.contains(wording))
+            .map_or("index.blocks.write", |(_, block)| block);
+        Some(IndexBlock { index, block })
+    }
+
+    /// The distinct blocks of failures
+    pub fn of_failures<'a>(failures: impl IntoIterator<Item = &'a Failure>) -> Vec<Self> {
+        failures
+            .into_iter()
+            .filter_map(|failure| {
+                IndexBlock::of(
+                    failure.index.as_deref(),
+                    &failure.reason.r#type,
+                    &failure.reason.reason,
+                )
+            })
+            .sorted()
+            .dedup()
+            .collect()
+    }
+}
+
+/// Why the block was set and how to remove it
+impl fmt::Display for IndexBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let why = match self.block {
+            FLOOD_STAGE_BLOCK => {
+                "set when a node exceeds the flood-stage disk watermark: free some disk space"
+            }
+            "index.blocks.write" => {
+                "set on frozen indices (unfreeze it with POST {index}/_unfreeze) or on purpose: \
+                 check why it was set"
+            }
+            _ => "check why it was set",
+        };
+        write!(
+            f,
+            "index {} has {} set, {}, then remove it with PUT {}/_settings {{\"{}\": null}}",
+            self.index,
+            self.block,
+            why.replace("{index}", &self.index),
+            self.index,
+            self.block
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailureKind {
+    Retryable,
+    /// eg: mapper_parsing_exception, index_closed_exception
+    Permanent,
+    /// Aborted on a version conflict, retrying would abort again on the next change
+    VersionConflict,
+}
+
+impl FailureKind {
+    pub fn of(failure_type: &str) -> Self {
+        if RETRYABLE_FAILURE_TYPES.contains(&failure_type) {
+            FailureKind::Retryable
+        } else if failure_type == VERSION_CONFLICT_TYPE {
+            FailureKind::VersionConflict
+        } else {
+            FailureKind::Permanent
+        }
+    }
+}
+
+/// Whether the failure is caused by the load of the cluster, see --auto-throttle
+pub fn is_load_related(failure_type: &str) -> bool {
+    LOAD_FAILURE_TYPES.contains(&failure_type)
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::Retryable => write!(f, "retryable"),
+            FailureKind::Permanent => write!(f, "permanent"),
+            FailureKind::VersionConflict => write!(f, "version conflict"),
+        }
+    }
+}
+
+/// Failures of the same type on the same index
+struct FailureGroup<'a> {
+    index: &'a str,
+    r#type: &'a str,
+    count: usize,
+    first: &'a Failure,
+}
+
+impl fmt::Display for FailureGroup<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = FailureKind::of(self.r#type);
+        if kind == FailureKind::VersionConflict {
+            return write!(
+                f,
+                "  [{}] {} — {} documents changed during the delete (--abort-on-conflict), eg: document {}",
+                kind,
+                self.index,
+                self.count,
+                self.first.id.as_deref().unwrap_or("?")
+            );
+        }
+        write!(
+            f,
+            "  [{}] {} — {} ×{} (sample: {})",
+            kind,
+            self.index,
+            self.r#type,
+            self.count,
+            snippet(&self.first.reason.reason, SAMPLE_MAX_CHARS)
+        )
+    }
+}
+
+/// The failures grouped by index and type, one group per line from the most frequent, at most
+/// --max-failure-groups of them
+pub fn aggregated<'a>(failures: impl IntoIterator<Item = &'a Failure>, opt: &Opt) -> String {
+    let groups = failures
+        .into_iter()
+        .into_group_map_by(|failure| {
+            (
+                failure.index.as_deref().unwrap_or("?"),
+                failure.reason.r#type.as_str(),
+            )
+        })
+        .into_iter()
+        .map(|((index, r#type), failures)| FailureGroup {
+            index,
+            r#type,
+            count: failures.len(),
+            first: failures[0],
+        })
+        .sorted_by(|a, b| (b.count, a.index, a.r#type).cmp(&(a.count, b.index, b.r#type)))
+        .collect::<Vec<_>>();
+    let mut lines = groups
+        .iter()
+        .take(opt.max_failure_groups)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let hidden = groups.len().saturating_sub(opt.max_failure_groups);
+    if hidden > 0 {
+        let details = match &opt.failure_log {
+            Some(path) => format!("every failure is in {}", path.display()),
+            None => "use --failure-log to keep every failure".to_string(),
+        };
+        lines.push(format!("  and {} more ({})", hidden, details));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailureKind;
+
+    #[test]
+    fn classifies_the_failure_types() {
+        for (failure_type, kind) in [
+            ("es_rejected_execution_exception", FailureKind::Retryable),
+            ("search_context_missing_exception", FailureKind::Retryable),
+            ("node_disconnected_exception", FailureKind::Retryable),
+            ("node_not_connected_exception", FailureKind::Retryable),
+            (
+                "version_conflict_engine_exception",
+                FailureKind::VersionConflict,
+            ),
+            // a block stays until someone removes it
+            ("cluster_block_exception", FailureKind::Permanent),
+            ("mapper_parsing_exception", FailureKind::Permanent),
+            ("some_future_exception", FailureKind::Permanent),
+            ("", FailureKind::Permanent),
+        ] {
+            assert_eq!(FailureKind::of(failure_type), kind, "{}", failure_type);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! --forcemerge: the segments of the target indices merged once the delete completed, deleted
+//! documents only free their disk space when the segments holding them are merged away.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use indicatif::ProgressStyle;
+use tokio::time::sleep;
+
+use crate::{
+    append_index_options, error_for_status, format_duration, get_task, index_url, lock,
+    output::Output, submitted_task, EsClient, Opt, TaskId,
+};
+
+/// Force merge task waited for, a Ctrl-C then stops waiting for it rather than interrupting the
+/// completed delete
+static RUNNING: Mutex<Option<TaskId>> = Mutex::new(None);
+
+/// What the force merge does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForceMerge {
+    /// Only merge the segments with deleted documents
+    ExpungeDeletes,
+    /// Merge the segments of each shard down to this number
+    MaxNumSegments(u32),
+}
+
+impl std::str::FromStr for ForceMerge {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        if value == "only_expunge_deletes" {
+            return Ok(ForceMerge::ExpungeDeletes);
+        }
+        match value
+            .strip_prefix("max_num_segments=")
+            .map(str::parse::<u32>)
+        {
+            Some(Ok(segments)) if segments > 0 => Ok(ForceMerge::MaxNumSegments(segments)),
+            _ => anyhow::bail!("expected only_expunge_deletes or max_num_segments=N"),
+        }
+    }
+}
+
+impl std::fmt::Display for ForceMerge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForceMerge::ExpungeDeletes => write!(f, "only_expunge_deletes"),
+            ForceMerge::MaxNumSegments(segments) => write!(f, "max_num_segments={}", segments),
+        }
+    }
+}
+
+/// The force merge task waited for, if any
+pub fn running() -> Option<TaskId> {
+    lock(&RUNNING).clone()
+}
+
+/// Force merge the target indices in a background task and wait for it. A failure is only a
+/// warning, the delete itself succeeded: returns whether the force merge completed.
+pub async fn run(opt: &Opt, mode: ForceMerge, client: &EsClient) -> bool {
+    let output = Output::new(opt, None);
+    output.set_style(
+        ProgressStyle::default_spinner().template("{spinner} [{elapsed_precise}] {msg}"),
+    );
+    let _ticking = output.enable_steady_tick();
+    let started = Instant::now();
+    let task_id = match submit(opt, mode, client).await {
+        Ok(task_id) => task_id,
+        Err(e) => {
+            output.abandon();
+            eprintln!(
+                "Warning: the documents were deleted but the force merge could not start: {}",
+                e
+            );
+            return false;
+        }
+    };
+    *lock(&RUNNING) = Some(task_id.clone());
+    output.notify(format!(
+        "Force merging indices matching '{}' ({}), task {}",
+        opt.index, mode, task_id.0
+    ));
+    let result = loop {
+        // a force merge task has no delete by query counters, only its completion matters
+        match get_task(&task_id, opt, client).await {
+            Ok(response) if response.completed => break response.error.map(|e| e.to_string()),
+            Ok(_) => output.set_message(format!(
+                "Force merging indices matching '{}' ({})...",
+                opt.index, mode
+            )),
+            Err(e) => output.println(format!("Unable to get the force merge task: {}", e)),
+        }
+        sleep(Duration::from_secs(opt.poll_interval_secs.max(1))).await;
+    };
+    lock(&RUNNING).take();
+    match result {
+        None => {
+            output.finish_with_message(format!(
+                "Force merge completed in {}",
+                format_duration(started.elapsed())
+            ));
+            true
+        }
+        Some(error) => {
+            output.abandon();
+            eprintln!(
+                "Warning: the documents were deleted but the force merge failed: {}",
+                error
+            );
+            false
+        }
+    }
+}
+
+async fn submit(opt: &Opt, mode: ForceMerge, client: &EsClient) -> anyhow::Result<TaskId> {
+    let mut url = index_url(opt, "_forcemerge")?;
+    {
+        let mut params = url.query_pairs_mut();
+        params.append_pair("wait_for_completion", "false");
+        match mode {
+            ForceMerge::ExpungeDeletes => params.append_pair("only_expunge_deletes", "true"),
+            ForceMerge::MaxNumSegments(segments) => {
+                params.append_pair("max_num_segments", &segments.to_string())
+            }
+        };
+        append_index_options(opt, &mut params);
+    }
+    let response = error_for_status(client.send(client.post(url)).await?).await?;
+    submitted_task(response, client).await
+}
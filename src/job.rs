@@ -0,0 +1,341 @@
+//! The delete as a library: [`DeleteByQueryJob`] submits and monitors the delete by query task
+//! like the command line tool (retries, throttling, conflicts), reporting its progress to a
+//! callback instead of a progress bar.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures_util::{future, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{watch, Notify},
+    time::timeout,
+};
+use tokio_stream::wrappers::WatchStream;
+use url::Url;
+
+use crate::{
+    build_client, cancel_task, cluster::Flavor, is_not_found, lock, output::Output, read_query,
+    run_delete_by_query, AttemptStats, Deadline, FailureReport, Opt, ProgressAccounting,
+    RunSummary, RunTracking, TaskId,
+};
+
+/// Progress of the running task, reported at each poll
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub task_id: TaskId,
+    /// Attempt of the delete from 1, a failed task is submitted again with --max-retries
+    pub attempt: u32,
+    /// Documents to delete over all the attempts
+    pub total: u64,
+    /// Documents deleted so far over all the attempts
+    pub deleted: u64,
+    pub version_conflicts: u64,
+    pub batches: u64,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Stops a running job: its task is cancelled and `run` returns once the task stopped
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<(AtomicBool, Notify)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.0.store(true, Ordering::SeqCst);
+        self.inner.1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    async fn cancelled(&self) {
+        loop {
+            // registered before the check, so that a concurrent cancel is not missed
+            let notified = self.inner.1.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What a job did, its counters cover all the attempts
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct JobOutcome {
+    pub deleted: u64,
+    pub version_conflicts: u64,
+    pub noops: u64,
+    /// Documents updated and created, that a delete never does unless it went through an
+    /// ingest pipeline
+    pub updated: u64,
+    pub created: u64,
+    pub batches: u64,
+    pub attempts: u32,
+    /// Submitted tasks, one per attempt
+    pub task_ids: Vec<TaskId>,
+    /// What each attempt did
+    pub per_attempt: Vec<AttemptStats>,
+    /// Distinct failures of the failed attempts
+    pub failures: Vec<FailureReport>,
+    /// Stopped by its cancellation token
+    pub cancelled: bool,
+    /// Why the delete stopped before completing
+    pub error: Option<String>,
+}
+
+impl From<RunSummary> for JobOutcome {
+    fn from(summary: RunSummary) -> Self {
+        JobOutcome {
+            deleted: summary.deleted,
+            version_conflicts: summary.version_conflicts,
+            noops: summary.noops,
+            updated: summary.updated,
+            created: summary.created,
+            batches: summary.batches,
+            attempts: summary.attempts,
+            task_ids: summary.task_ids,
+            per_attempt: summary.per_attempt,
+            failures: summary.failures,
+            cancelled: false,
+            error: None,
+        }
+    }
+}
+
+/// A delete by query, eg:
+///
+/// ```no_run
+/// # async fn retention() -> anyhow::Result<()> {
+/// use elasticsearch_delete_by_query::DeleteByQueryJob;
+///
+/// let outcome = DeleteByQueryJob::new(
+///     "http://localhost:9200".parse()?,
+///     "logs-*",
+///     serde_json::json!({"range": {"@timestamp": {"lt": "now-90d"}}}),
+/// )?
+/// .requests_per_second(500.0)
+/// .on_progress(|progress| println!("{}/{}", progress.deleted, progress.total))
+/// .run()
+/// .await?;
+/// println!("{} documents deleted", outcome.deleted);
+/// # Ok(())
+/// # }
+/// ```
+pub struct DeleteByQueryJob {
+    opt: Opt,
+    client: Option<reqwest::Client>,
+    on_progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+}
+
+impl DeleteByQueryJob {
+    /// Delete the documents of `index` (an index expression, eg: `logs-*,-logs-keep`) matching
+    /// `query`. The other options default like the command line ones, the `ESDBQ_` environment
+    /// variables do not apply.
+    pub fn new(url: Url, index: &str, query: serde_json::Value) -> anyhow::Result<Self> {
+        let opt = Opt::default_for_job(url, index, &query);
+        Ok(DeleteByQueryJob {
+            opt,
+            client: None,
+            on_progress: None,
+            cancellation: CancellationToken::new(),
+        })
+    }
+
+    /// HTTP client sending the requests (eg: with the TLS settings of the caller)
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.opt.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.opt.user = Some(user.into());
+        self.opt.password = Some(password.into());
+        self
+    }
+
+    /// Throttle the delete, -1 for unthrottled
+    pub fn requests_per_second(mut self, requests_per_second: f64) -> Self {
+        self.opt.requests_per_second = Some(requests_per_second);
+        self
+    }
+
+    /// Halve the throttle of the next attempt after failures of an overloaded cluster, down to
+    /// `floor` requests per second, and raise it back after the attempts without failures
+    pub fn auto_throttle(mut self, floor: f64) -> Self {
+        self.opt.auto_throttle = true;
+        self.opt.auto_throttle_floor = floor;
+        self
+    }
+
+    /// Number of slices, a number or auto
+    pub fn slices(mut self, slices: impl Into<String>) -> Self {
+        self.opt.slices = Some(slices.into());
+        self
+    }
+
+    pub fn scroll_size(mut self, scroll_size: u64) -> Self {
+        self.opt.scroll_size = Some(scroll_size);
+        self
+    }
+
+    pub fn max_docs(mut self, max_docs: u64) -> Self {
+        self.opt.max_docs = Some(max_docs);
+        self
+    }
+
+    /// Submit the delete again this many times when its task fails
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.opt.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Remove the flood-stage read-only block of the indices failing the delete with
+    /// cluster_block_exception, then retry once
+    pub fn clear_read_only_block(mut self) -> Self {
+        self.opt.clear_read_only_block = true;
+        self
+    }
+
+    /// Fail on the first version conflict instead of skipping the changed documents
+    pub fn abort_on_conflict(mut self) -> Self {
+        self.opt.abort_on_conflict = true;
+        self
+    }
+
+    /// Cancel the task and fail as soon as it reports documents updated, created or skipped as
+    /// noops, instead of warning
+    pub fn strict_counters(mut self) -> Self {
+        self.opt.strict_counters = true;
+        self
+    }
+
+    /// Time between two task status requests
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.opt.poll_interval_secs = poll_interval.as_secs().max(1);
+        self.opt.poll_timeout_secs = 0;
+        self
+    }
+
+    /// Time between the submission of a task and its first status request
+    pub fn initial_wait(mut self, initial_wait: Duration) -> Self {
+        self.opt.initial_wait_secs = initial_wait.as_secs();
+        self
+    }
+
+    /// Time to wait after a first failed attempt before retrying, doubled after each
+    /// consecutive failed attempt
+    pub fn retry_pause(mut self, retry_pause: Duration) -> Self {
+        self.opt.pause_initial_secs = retry_pause.as_secs();
+        self
+    }
+
+    /// Update the matching documents with this painless script instead of deleting them
+    pub fn script(mut self, source: impl Into<String>) -> Self {
+        self.opt.script = Some(serde_json::json!({ "source": source.into(), "lang": "painless" }));
+        self
+    }
+
+    /// Called at each poll of the running task
+    pub fn on_progress(mut self, on_progress: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    /// Token stopping the job once it runs
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Run the delete until its task completes, fails for good or is cancelled. Only the errors
+    /// before submitting (eg: an invalid query) are returned as errors, a failed delete is a
+    /// [`JobOutcome::error`].
+    pub async fn run(self) -> anyhow::Result<JobOutcome> {
+        let opt = &self.opt;
+        let query = read_query(opt)?;
+        let mut client = build_client(opt)?;
+        if let Some(http) = self.client {
+            client.client = http;
+        }
+        client.flavor.get_or_insert(Flavor::Elasticsearch);
+        let output = Output::callback(opt, self.on_progress);
+        client.progress = output.clone();
+        let (active_tasks_sender, active_tasks) = watch::channel(HashSet::<TaskId>::new());
+        let summary = Mutex::new(RunSummary::default());
+        let tracking = RunTracking {
+            active_tasks: &active_tasks_sender,
+            summary: &summary,
+            failure_log: None,
+            timeline_csv: None,
+            state: None,
+            cancelled: &self.cancellation.inner.0,
+            deadline: &Deadline::default(),
+            retry_notified: AtomicBool::new(false),
+            auto_throttle: Mutex::new(None),
+            counters_warned: AtomicBool::new(false),
+        };
+        let mut progress = ProgressAccounting::default();
+        let error = tokio::select! {
+            result = run_delete_by_query(opt, &query, &client, &output, &tracking, &mut progress) => {
+                result.err()
+            }
+            e = cancel_on_token(&self.cancellation, opt, &client, active_tasks) => Some(e),
+        };
+        let mut outcome = JobOutcome::from(lock(&summary).clone());
+        outcome.cancelled = self.cancellation.is_cancelled();
+        outcome.error = error.map(|e| e.to_string());
+        Ok(outcome)
+    }
+}
+
+/// Once cancelled, cancel the running task and the ones submitted after it: the delete then
+/// stops like on a Ctrl-C. Only returns on a failed cancellation.
+async fn cancel_on_token(
+    token: &CancellationToken,
+    opt: &Opt,
+    client: &crate::EsClient,
+    active_tasks: watch::Receiver<HashSet<TaskId>>,
+) -> anyhow::Error {
+    token.cancelled().await;
+    let mut active_tasks = WatchStream::new(active_tasks);
+    let mut cancelled = HashSet::new();
+    while let Some(task_ids) = active_tasks.next().await {
+        for task_id in task_ids {
+            if !cancelled.insert(task_id.clone()) {
+                continue;
+            }
+            match timeout(
+                Duration::from_secs(opt.cancel_timeout_secs),
+                cancel_task(&task_id, opt, client),
+            )
+            .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) if is_not_found(&e) => {}
+                Ok(Err(e)) => return e,
+                Err(_) => return anyhow::anyhow!("the cancel request of {} timed out", task_id.0),
+            }
+        }
+    }
+    future::pending().await
+}
@@ -0,0 +1,93 @@
+//! login/logout: the credentials of a cluster kept in the OS keyring, keyed by its url, and used
+//! when none are given.
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use url::Url;
+
+use crate::display_url;
+
+const SERVICE: &str = env!("CARGO_PKG_NAME");
+
+/// Keyring entry, recording the authentication scheme of the credentials
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum StoredCredentials {
+    Basic { user: String, password: String },
+    ApiKey { api_key: String },
+}
+
+/// Entries are keyed by the url of the cluster, without its credentials
+fn entry(url: &Url) -> keyring::Entry {
+    let url = display_url(url);
+    keyring::Entry::new(SERVICE, url.as_str().trim_end_matches('/'))
+}
+
+pub fn store(url: &Url, credentials: &StoredCredentials) -> anyhow::Result<()> {
+    entry(url)
+        .set_password(&serde_json::to_string(credentials)?)
+        .map_err(|e| anyhow::anyhow!("Unable to store the credentials in the keyring: {}", e))
+}
+
+/// Remove the credentials of the cluster, false if there were none
+pub fn remove(url: &Url) -> anyhow::Result<bool> {
+    match entry(url).delete_password() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => anyhow::bail!("Unable to remove the credentials from the keyring: {}", e),
+    }
+}
+
+/// Credentials of the cluster, None when there are none or the keyring is unreachable (eg: no
+/// secret service daemon on a headless server): the other credential sources are tried next
+pub fn lookup(url: &Url) -> Option<StoredCredentials> {
+    let secret = match entry(url).get_password() {
+        Ok(secret) => secret,
+        Err(keyring::Error::NoEntry) => return None,
+        Err(e) => {
+            debug!("keyring unavailable: {}", e);
+            return None;
+        }
+    };
+    match serde_json::from_str(&secret) {
+        Ok(credentials) => Some(credentials),
+        Err(e) => {
+            eprintln!(
+                "Warning: invalid keyring entry for {}, log in again: {}",
+                display_url(url),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Read a line from stdin without echoing it on a terminal
+#[cfg(unix)]
+pub fn read_secret(prompt: &str) -> anyhow::Result<String> {
+    eprint!("{}", prompt);
+    // SAFETY: tcgetattr and tcsetattr only access the termios struct they are given
+    let mut terminal = unsafe { std::mem::zeroed::<libc::termios>() };
+    let is_terminal = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut terminal) } == 0;
+    if is_terminal {
+        let mut silent = terminal;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent) };
+    }
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if is_terminal {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &terminal) };
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(not(unix))]
+pub fn read_secret(prompt: &str) -> anyhow::Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
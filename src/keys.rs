@@ -0,0 +1,187 @@
+//! Keyboard controls of the running tasks while the progress bar is drawn on a terminal: `p`
+//! pauses the tasks, `r` restores their throttle, `+`/`-` speed them up or slow them down by
+//! --throttle-step and `?` shows the keys. Only the echo and the line buffering of the terminal
+//! are turned off while reading the keys: Ctrl-C still interrupts the run.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use futures_util::future;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// The key map shown by `?`
+pub const HELP: &str = "keys: p pause • r resume • + faster • - slower";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Pause,
+    Resume,
+    Faster,
+    Slower,
+    Help,
+}
+
+impl Key {
+    fn of(byte: u8) -> Option<Self> {
+        match byte {
+            b'p' => Some(Key::Pause),
+            b'r' => Some(Key::Resume),
+            // `=` is `+` without shift
+            b'+' | b'=' => Some(Key::Faster),
+            b'-' => Some(Key::Slower),
+            b'?' | b'h' => Some(Key::Help),
+            _ => None,
+        }
+    }
+}
+
+/// The keyboard reader shared by the tasks monitored at the same time
+struct Reader {
+    keys: broadcast::Sender<Key>,
+    listeners: usize,
+    stop: Arc<AtomicBool>,
+    /// Settings of the terminal, restored once stopped
+    #[cfg(unix)]
+    terminal: libc::termios,
+}
+
+static READER: Mutex<Option<Reader>> = Mutex::new(None);
+/// Set on Ctrl-C: the keyboard is not read again, it may be prompted for
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+fn reader() -> MutexGuard<'static, Option<Reader>> {
+    READER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The keys pressed while monitoring a task
+pub struct Keys {
+    receiver: broadcast::Receiver<Key>,
+}
+
+impl Keys {
+    /// Start reading the keyboard, `None` unless stdin and stdout are terminals
+    pub fn listen() -> Option<Self> {
+        if STOPPED.load(Ordering::SeqCst)
+            || !atty::is(atty::Stream::Stdin)
+            || !atty::is(atty::Stream::Stdout)
+        {
+            return None;
+        }
+        let mut reader = reader();
+        if reader.is_none() {
+            *reader = start();
+        }
+        let reader = reader.as_mut()?;
+        reader.listeners += 1;
+        Some(Keys {
+            receiver: reader.keys.subscribe(),
+        })
+    }
+
+    /// The next key pressed, pending forever once the keyboard is not read anymore
+    pub async fn next(&mut self) -> Key {
+        loop {
+            match self.receiver.recv().await {
+                Ok(key) => return key,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return future::pending().await,
+            }
+        }
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        let mut reader = reader();
+        if let Some(listening) = reader.as_mut() {
+            listening.listeners -= 1;
+            if listening.listeners == 0 {
+                stop_reader(&mut reader);
+            }
+        }
+    }
+}
+
+/// Stop reading the keyboard for good and restore the terminal, eg: before prompting on Ctrl-C
+pub fn stop() {
+    STOPPED.store(true, Ordering::SeqCst);
+    stop_reader(&mut reader());
+}
+
+fn stop_reader(reader: &mut Option<Reader>) {
+    if let Some(reader) = reader.take() {
+        reader.stop.store(true, Ordering::SeqCst);
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &reader.terminal)
+        };
+    }
+}
+
+#[cfg(unix)]
+fn start() -> Option<Reader> {
+    let mut terminal = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut terminal) } != 0 {
+        return None;
+    }
+    // each key as soon as it is pressed, without echo: ISIG is kept for Ctrl-C
+    let mut keys_mode = terminal;
+    keys_mode.c_lflag &= !(libc::ICANON | libc::ECHO);
+    keys_mode.c_cc[libc::VMIN] = 1;
+    keys_mode.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys_mode) } != 0 {
+        return None;
+    }
+    let (keys, _) = broadcast::channel(16);
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let keys = keys.clone();
+        let stop = stop.clone();
+        thread::spawn(move || read_keys(&keys, &stop));
+    }
+    Some(Reader {
+        keys,
+        listeners: 0,
+        stop,
+        terminal,
+    })
+}
+
+#[cfg(not(unix))]
+fn start() -> Option<Reader> {
+    None
+}
+
+/// Read stdin until stopped, waking up regularly to notice it: once stopped the pending input is
+/// left to the prompts
+#[cfg(unix)]
+fn read_keys(keys: &broadcast::Sender<Key>, stop: &AtomicBool) {
+    let mut stdin = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    while !stop.load(Ordering::SeqCst) {
+        let ready = unsafe { libc::poll(&mut stdin, 1, 100) };
+        if ready < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return;
+        }
+        if ready <= 0 || stop.load(Ordering::SeqCst) {
+            continue;
+        }
+        let mut byte = 0u8;
+        if unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } != 1 {
+            return;
+        }
+        if let Some(key) = Key::of(byte) {
+            let _ = keys.send(key);
+        }
+    }
+}
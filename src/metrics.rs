@@ -0,0 +1,277 @@
+//! --metrics-listen and --pushgateway-url: the progress of the run as Prometheus metrics, served
+//! on /metrics or pushed to a Pushgateway. The polling loop only records the last task status,
+//! the metrics are rendered when scraped or pushed.
+
+use std::{
+    collections::BTreeMap, convert::Infallible, fmt::Write, net::SocketAddr, sync::Mutex,
+    time::Duration,
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use tokio::{sync::oneshot, task::JoinHandle, time::sleep};
+use url::Url;
+
+use crate::{display_url, segments_url, Opt, Outcome, TaskStatus};
+
+/// Pushgateway job of the pushed metrics
+const PUSH_JOB: &str = "elasticsearch_delete_by_query";
+
+/// Name, type, help and value of the metrics of each cluster
+type Metric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&Snapshot) -> f64,
+);
+
+const METRICS: [Metric; 7] = [
+    (
+        "esdbq_docs_total",
+        "gauge",
+        "Documents to process over all the attempts",
+        |s| s.total as f64,
+    ),
+    (
+        "esdbq_docs_deleted",
+        "counter",
+        "Documents deleted (or updated) over all the attempts",
+        |s| s.deleted as f64,
+    ),
+    (
+        "esdbq_version_conflicts",
+        "gauge",
+        "Version conflicts of the running task",
+        |s| s.version_conflicts as f64,
+    ),
+    (
+        "esdbq_batches",
+        "gauge",
+        "Scroll batches of the running task",
+        |s| s.batches as f64,
+    ),
+    (
+        "esdbq_bulk_retries",
+        "gauge",
+        "Bulk retries of the running task",
+        |s| s.bulk_retries as f64,
+    ),
+    (
+        "esdbq_throttled_seconds",
+        "gauge",
+        "Time the running task was throttled",
+        |s| s.throttled_millis as f64 / 1000.0,
+    ),
+    (
+        "esdbq_attempt",
+        "gauge",
+        "Attempt of the delete, from 1",
+        |s| s.attempt as f64,
+    ),
+];
+
+/// Last status of each cluster, `None` until the metrics are exported
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Registry {
+    /// By cluster, empty when the delete runs on a single one
+    clusters: BTreeMap<String, Snapshot>,
+    outcome: Option<Outcome>,
+    /// Incremented on each change, to push only the changes
+    generation: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Snapshot {
+    total: u64,
+    deleted: u64,
+    version_conflicts: i64,
+    batches: i64,
+    bulk_retries: i64,
+    throttled_millis: i64,
+    attempt: u32,
+}
+
+fn registry() -> std::sync::MutexGuard<'static, Option<Registry>> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Record a task status: `total` and `deleted` cover all the attempts, the other counters are
+/// the ones of the running task. Does nothing unless the metrics are exported.
+pub fn record(cluster: Option<&str>, total: u64, deleted: u64, status: &TaskStatus, attempt: u32) {
+    if let Some(registry) = registry().as_mut() {
+        registry.clusters.insert(
+            cluster.unwrap_or_default().to_string(),
+            Snapshot {
+                total,
+                deleted,
+                version_conflicts: status.version_conflicts,
+                batches: status.batches,
+                bulk_retries: status.retries.bulk,
+                throttled_millis: status.throttled_millis,
+                attempt,
+            },
+        );
+        registry.generation += 1;
+    }
+}
+
+/// The metrics in the Prometheus text format
+fn render() -> String {
+    let guard = registry();
+    let registry = match guard.as_ref() {
+        Some(registry) => registry,
+        None => return String::new(),
+    };
+    let mut text = String::new();
+    for (name, kind, help, value) in &METRICS {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for (cluster, snapshot) in &registry.clusters {
+            let _ = writeln!(text, "{}{} {}", name, labels(cluster), value(snapshot));
+        }
+    }
+    let _ = writeln!(
+        text,
+        "# HELP esdbq_up Whether the run is in progress\n# TYPE esdbq_up gauge\nesdbq_up {}",
+        registry.outcome.is_none() as u8
+    );
+    if let Some(outcome) = registry.outcome {
+        let outcome = serde_json::to_value(outcome).unwrap_or_default();
+        let _ = writeln!(
+            text,
+            "# HELP esdbq_outcome How the run ended\n# TYPE esdbq_outcome gauge\nesdbq_outcome{{outcome={}}} 1",
+            outcome
+        );
+    }
+    text
+}
+
+fn labels(cluster: &str) -> String {
+    if cluster.is_empty() {
+        String::new()
+    } else {
+        // a JSON string is a valid label value: quotes and backslashes escaped
+        format!("{{cluster={}}}", serde_json::Value::from(cluster))
+    }
+}
+
+/// A background export and its stop signal
+type Export = (oneshot::Sender<()>, JoinHandle<()>);
+
+/// The running exports, stopped at the end of the run
+pub struct Metrics {
+    server: Option<Export>,
+    pusher: Option<Export>,
+}
+
+impl Metrics {
+    /// Start serving and pushing the metrics, `None` without --metrics-listen nor
+    /// --pushgateway-url
+    pub fn start(opt: &Opt) -> anyhow::Result<Option<Self>> {
+        if opt.metrics_listen.is_none() && opt.pushgateway_url.is_none() {
+            return Ok(None);
+        }
+        *registry() = Some(Registry::default());
+        let server = opt.metrics_listen.map(serve).transpose()?;
+        let pusher = match &opt.pushgateway_url {
+            Some(url) => Some(push(
+                segments_url(url, ["metrics", "job", PUSH_JOB])?,
+                Duration::from_secs(opt.poll_interval_secs.max(1)),
+            )),
+            None => None,
+        };
+        Ok(Some(Metrics { server, pusher }))
+    }
+
+    /// Record the outcome, push it and stop the server
+    pub async fn stop(self, outcome: Outcome) {
+        if let Some(registry) = registry().as_mut() {
+            registry.outcome = Some(outcome);
+            registry.generation += 1;
+        }
+        for (stop, handle) in self.pusher.into_iter().chain(self.server) {
+            let _ = stop.send(());
+            let _ = handle.await;
+        }
+    }
+}
+
+fn serve(addr: SocketAddr) -> anyhow::Result<Export> {
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = Server::try_bind(&addr)
+        .map_err(|e| anyhow::anyhow!("Unable to listen on {} for the metrics: {}", addr, e))?
+        .serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let response = if request.uri().path() == "/metrics" {
+                    Response::builder()
+                        .header("content-type", "text/plain; version=0.0.4")
+                        .body(Body::from(render()))
+                } else {
+                    Response::builder().status(404).body(Body::empty())
+                };
+                Ok::<_, Infallible>(response.expect("valid response"))
+            }))
+        }))
+        .with_graceful_shutdown(async {
+            let _ = stopped.await;
+        });
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("Warning: the metrics endpoint failed: {}", e);
+        }
+    });
+    Ok((stop, handle))
+}
+
+/// Push the changed metrics every `interval`, and a last time once stopped
+fn push(url: Url, interval: Duration) -> Export {
+    let (stop, mut stopped) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut pushed = 0;
+        let mut failing = false;
+        loop {
+            let last = tokio::select! {
+                _ = sleep(interval) => false,
+                _ = &mut stopped => true,
+            };
+            let generation = registry()
+                .as_ref()
+                .map_or(0, |registry| registry.generation);
+            if generation != pushed || last {
+                let result = client
+                    .put(url.clone())
+                    .timeout(interval.max(Duration::from_secs(5)))
+                    .body(render())
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) => {
+                        pushed = generation;
+                        failing = false;
+                    }
+                    // warn once per outage, the next pushes will catch up
+                    Err(e) if !failing => {
+                        eprintln!(
+                            "Warning: unable to push the metrics to {}: {}",
+                            display_url(&url),
+                            e
+                        );
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            if last {
+                break;
+            }
+        }
+    });
+    (stop, handle)
+}
@@ -0,0 +1,197 @@
+//! Credentials of the cluster looked up in ~/.netrc (or $NETRC), like curl does, when none were
+//! given.
+
+use std::path::{Path, PathBuf};
+
+/// Login and password of a netrc entry
+pub struct Credentials {
+    pub login: String,
+    pub password: Option<String>,
+}
+
+/// $NETRC, or ~/.netrc
+fn path() -> Option<PathBuf> {
+    std::env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".netrc")))
+}
+
+/// Credentials of the machine `host`, or of the default entry. Unreadable or invalid files are
+/// only reported: the cluster may not need credentials after all.
+pub fn lookup(host: &str) -> Option<(Credentials, PathBuf)> {
+    let path = path()?;
+    match read(&path, host) {
+        Ok(credentials) => credentials.map(|credentials| (credentials, path)),
+        Err(warning) => {
+            eprintln!("Warning: {}", warning);
+            None
+        }
+    }
+}
+
+/// Credentials of `host` in the file at `path`, or the warning explaining why it is ignored
+fn read(path: &Path, host: &str) -> Result<Option<Credentials>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("unable to read {}: {}", path.display(), e)),
+    };
+    if let Some(problem) = permission_problem(path) {
+        return Err(format!("{} ignored: {}", path.display(), problem));
+    }
+    find(&text, host).map_err(|e| format!("{} ignored: {}", path.display(), e))
+}
+
+/// The file holds passwords, it must only be readable by its owner
+#[cfg(unix)]
+fn permission_problem(path: &Path) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path).ok()?.permissions().mode();
+    (mode & 0o077 != 0).then(|| {
+        format!(
+            "readable by other users (mode {:o}), chmod 600 it",
+            mode & 0o777
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn permission_problem(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Entry of the exact `host`, the default entry otherwise, whatever their order in the file
+fn find(text: &str, host: &str) -> anyhow::Result<Option<Credentials>> {
+    let mut default = None;
+    let mut lines = text.lines();
+    // tokens of the current entry: None before the first machine/default
+    let mut entry: Option<(Option<String>, Option<String>, Option<String>)> = None;
+    let mut entries = Vec::new();
+    while let Some(line) = lines.next() {
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token.starts_with('#') {
+                break;
+            }
+            let mut value = |name: &str| {
+                tokens
+                    .next()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("{} without a value", name))
+            };
+            match token {
+                "machine" => {
+                    entries.extend(entry.take());
+                    entry = Some((Some(value("machine")?), None, None));
+                }
+                "default" => {
+                    entries.extend(entry.take());
+                    entry = Some((None, None, None));
+                }
+                "login" | "password" | "account" => {
+                    let value = value(token)?;
+                    let entry = entry
+                        .as_mut()
+                        .ok_or_else(|| anyhow::anyhow!("{} outside of an entry", token))?;
+                    match token {
+                        "login" => entry.1 = Some(value),
+                        "password" => entry.2 = Some(value),
+                        _ => {}
+                    }
+                }
+                "macdef" => {
+                    // the macro runs until an empty line
+                    for line in lines.by_ref() {
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                other => anyhow::bail!("unexpected token '{}'", other),
+            }
+        }
+    }
+    entries.extend(entry);
+    for (machine, login, password) in entries {
+        let credentials = match login {
+            Some(login) => Credentials { login, password },
+            None => continue,
+        };
+        match machine {
+            Some(machine) if machine == host => return Ok(Some(credentials)),
+            Some(_) => {}
+            None => default = default.or(Some(credentials)),
+        }
+    }
+    Ok(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, read};
+
+    /// Login of the entry found for `host`
+    fn login(text: &str, host: &str) -> Option<String> {
+        find(text, host)
+            .unwrap()
+            .map(|credentials| credentials.login)
+    }
+
+    #[test]
+    fn matches_the_exact_host() {
+        let text = "machine es.example.com login elastic password changeme\n\
+                    machine example.com login other password secret";
+        assert_eq!(login(text, "es.example.com").as_deref(), Some("elastic"));
+        assert_eq!(login(text, "example.com").as_deref(), Some("other"));
+        for host in ["es.example.co", "es.example.com.evil", "eu.es.example.com"] {
+            assert_eq!(login(text, host), None, "{}", host);
+        }
+        let credentials = find(text, "es.example.com").unwrap().unwrap();
+        assert_eq!(credentials.password.as_deref(), Some("changeme"));
+    }
+
+    #[test]
+    fn honors_the_default_entry_last() {
+        // the default entry may come first, a machine entry still wins
+        let text = "default login anonymous\n\
+                    # staging\n\
+                    machine es.example.com\n  login elastic\n  password changeme\n";
+        assert_eq!(login(text, "es.example.com").as_deref(), Some("elastic"));
+        assert_eq!(
+            login(text, "other.example.com").as_deref(),
+            Some("anonymous")
+        );
+        let credentials = find(text, "other.example.com").unwrap().unwrap();
+        assert_eq!(credentials.password, None);
+        // an entry without login does not hide the default
+        let text = "machine es.example.com password changeme\ndefault login anonymous";
+        assert_eq!(login(text, "es.example.com").as_deref(), Some("anonymous"));
+    }
+
+    #[test]
+    fn warns_about_a_malformed_file() {
+        let path = std::env::temp_dir().join(format!("esdbq-netrc-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "machine es.example.com login elastic pasword changeme",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let warning = read(&path, "es.example.com").err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            warning,
+            Some(format!(
+                "{} ignored: unexpected token 'pasword'",
+                path.display()
+            ))
+        );
+        assert!(find("login elastic", "es.example.com").is_err());
+        assert!(find("machine", "es.example.com").is_err());
+    }
+}
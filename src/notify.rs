@@ -0,0 +1,199 @@
+//! --notify-webhook: a JSON payload (or a Slack message with --notify-format slack) posted when a
+//! run ends, and on its first retry. Notifying never fails the run: errors are only warnings.
+
+use std::time::Duration;
+
+use futures_util::future;
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    display_url, format_duration, output::format_thousands, FailureReport, Operation, Opt, Outcome,
+};
+
+/// Failures listed in a Slack message, the JSON payload has them all
+const SLACK_MAX_FAILURES: usize = 5;
+
+/// Format of the webhook payloads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotifyFormat {
+    Json,
+    /// Slack incoming webhook: the same data as a markdown message
+    Slack,
+}
+
+impl std::str::FromStr for NotifyFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "json" => Ok(NotifyFormat::Json),
+            "slack" => Ok(NotifyFormat::Slack),
+            _ => anyhow::bail!("expected json or slack, got {}", value),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    Completed,
+    /// A task completed with failures and is submitted again, only notified once per run
+    Retrying,
+    Cancelled,
+    Aborted,
+}
+
+impl NotifyEvent {
+    /// The event of a run that ended, none when nothing was deleted on purpose (eg: dry run)
+    pub fn of(outcome: Outcome) -> Option<Self> {
+        match outcome {
+            Outcome::Completed
+            | Outcome::RefreshFailed
+            | Outcome::ForceMergeFailed
+            | Outcome::MatchesRemain => Some(NotifyEvent::Completed),
+            Outcome::Interrupted
+            | Outcome::InterruptedTaskKept
+            | Outcome::DeadlineReached
+            | Outcome::ForceQuit
+            | Outcome::CancelFailed => Some(NotifyEvent::Cancelled),
+            Outcome::TaskVanished
+            | Outcome::StatusUnavailable
+            | Outcome::RetriesExhausted
+            | Outcome::TooManyConflicts
+            | Outcome::UnexpectedCounters
+            | Outcome::TooManyMatches
+            | Outcome::PreflightFailed
+            | Outcome::WrongCluster
+            | Outcome::NothingMatched
+            | Outcome::InvalidQuery
+            | Outcome::Unreachable
+            | Outcome::TaskNotFound
+            | Outcome::Error => Some(NotifyEvent::Aborted),
+            Outcome::DryRun
+            | Outcome::Preview
+            | Outcome::Detached
+            | Outcome::NotConfirmed
+            | Outcome::BadArguments
+            | Outcome::Skipped => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Notification {
+    pub event: NotifyEvent,
+    /// Outcome of the run, once it ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+    /// Url of the cluster, without its credentials
+    pub cluster: String,
+    pub index: String,
+    pub deleted: u64,
+    pub version_conflicts: u64,
+    pub failures: Vec<FailureReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Posts the notifications to every --notify-webhook
+#[derive(Clone)]
+pub struct Notifier {
+    webhooks: Vec<Url>,
+    format: NotifyFormat,
+    timeout: Duration,
+    operation: Operation,
+}
+
+impl Notifier {
+    /// None without --notify-webhook
+    pub fn of(opt: &Opt) -> Option<Self> {
+        if opt.notify_webhooks.is_empty() {
+            return None;
+        }
+        Some(Notifier {
+            webhooks: opt.notify_webhooks.clone(),
+            format: opt.notify_format,
+            timeout: Duration::from_secs(opt.notify_timeout_secs),
+            operation: Operation::of(opt),
+        })
+    }
+
+    /// Post the notifications, each webhook at most --notify-timeout
+    pub async fn send(&self, notifications: &[Notification]) {
+        let client = reqwest::Client::new();
+        let posts = notifications.iter().flat_map(|notification| {
+            let payload = match self.format {
+                NotifyFormat::Json => serde_json::to_value(notification).unwrap_or_default(),
+                NotifyFormat::Slack => serde_json::json!({ "text": self.slack(notification) }),
+            };
+            let client = &client;
+            self.webhooks.iter().map(move |webhook| {
+                let request = client
+                    .post(webhook.clone())
+                    .timeout(self.timeout)
+                    .json(&payload);
+                async move {
+                    if let Err(e) = request
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status())
+                    {
+                        eprintln!("Warning: unable to notify {}: {}", display_url(webhook), e);
+                    }
+                }
+            })
+        });
+        future::join_all(posts).await;
+    }
+
+    /// eg: :white_check_mark: *Delete by query completed* on `https://es:9200` `logs-*`
+    fn slack(&self, notification: &Notification) -> String {
+        let (emoji, what) = match notification.event {
+            NotifyEvent::Completed => (":white_check_mark:", "completed"),
+            NotifyEvent::Retrying => (":warning:", "failed, retrying"),
+            NotifyEvent::Cancelled => (":octagonal_sign:", "cancelled"),
+            NotifyEvent::Aborted => (":x:", "aborted"),
+        };
+        let mut text = format!(
+            "{} *{} by query {}* on `{}` `{}`\n{} documents {}, {} version conflicts",
+            emoji,
+            self.operation.title(),
+            what,
+            notification.cluster,
+            notification.index,
+            format_thousands(notification.deleted),
+            self.operation.done(),
+            format_thousands(notification.version_conflicts)
+        );
+        if let Some(duration_secs) = notification.duration_secs {
+            text.push_str(&format!(
+                ", in {}",
+                format_duration(Duration::from_secs_f64(duration_secs))
+            ));
+        }
+        if let Some(outcome) = notification.outcome {
+            text.push_str(&format!(" ({:?})", outcome));
+        }
+        if let Some(error) = &notification.error {
+            text.push_str(&format!("\n> {}", error));
+        }
+        for failure in notification.failures.iter().take(SLACK_MAX_FAILURES) {
+            text.push_str(&format!(
+                "\n• `{}` on {}: {}",
+                failure.r#type,
+                failure.index.as_deref().unwrap_or("?"),
+                failure.reason
+            ));
+        }
+        if notification.failures.len() > SLACK_MAX_FAILURES {
+            text.push_str(&format!(
+                "\n…and {} more failures",
+                notification.failures.len() - SLACK_MAX_FAILURES
+            ));
+        }
+        text
+    }
+}
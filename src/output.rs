@@ -0,0 +1,471 @@
+//! Where the progress and the messages of a run go: an animated progress bar on a terminal, plain
+//! status lines otherwise (cron, CI, --no-progress), only the messages with --progress jsonl,
+//! nothing with --quiet. The tracing logs go through it as well.
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::{
+    clock::DateTime, events::ProgressFormat, Operation, Opt, Progress, ProgressCallback, TaskId,
+    TaskStatus, Terminal,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Animated progress bar on stderr
+    Bar,
+    /// A status line on stderr at most every --log-interval
+    Lines,
+    /// The messages only, the progress is reported as --progress jsonl events
+    Quiet,
+    /// Neither the progress nor the messages (--quiet)
+    Silent,
+}
+
+/// Progress bar, or its line based replacement when stderr is not a terminal
+#[derive(Clone)]
+pub struct Output {
+    bar: ProgressBar,
+    mode: Mode,
+    /// Length of the bar, 0 until known: the bar is a spinner until then
+    length: Arc<AtomicU64>,
+    /// Style of the bar once its length is known
+    style: Arc<Mutex<Option<ProgressStyle>>>,
+    /// Prepended to the status lines (the index with --per-index)
+    prefix: String,
+    log_interval: Duration,
+    /// --tick-interval, zero without a steady tick
+    tick_interval: Duration,
+    last_status_line: Arc<Mutex<Option<Instant>>>,
+    /// --timestamp-format of the printed lines, no timestamp when empty
+    timestamp_format: String,
+    utc: bool,
+    /// Attempt of the running delete by query, shown from the first retry on
+    attempt: Arc<AtomicU32>,
+    /// Cluster of the run, when the delete runs on several
+    cluster: Option<String>,
+    operation: Operation,
+    /// Progress callback of a library job
+    on_progress: Option<ProgressCallback>,
+}
+
+impl Output {
+    /// Output of a run of `len` steps, a spinner until `set_progress` tells the length if unknown
+    pub fn new(opt: &Opt, len: Option<u64>) -> Self {
+        let mode = Self::mode(opt);
+        let bar = match mode {
+            Mode::Bar => ProgressBar::new(0),
+            Mode::Lines | Mode::Quiet | Mode::Silent => ProgressBar::hidden(),
+        };
+        Self::with_length(opt, mode, bar, len)
+    }
+
+    /// Output of a run of `len` steps displayed along other bars of `multi`
+    pub fn in_multi(opt: &Opt, multi: &MultiProgress, len: Option<u64>) -> Self {
+        let mode = Self::mode(opt);
+        let bar = match mode {
+            Mode::Bar => multi.add(ProgressBar::new(0)),
+            Mode::Lines | Mode::Quiet | Mode::Silent => ProgressBar::hidden(),
+        };
+        Self::with_length(opt, mode, bar, len)
+    }
+
+    fn with_length(opt: &Opt, mode: Mode, bar: ProgressBar, len: Option<u64>) -> Self {
+        let output = Self::with_bar(opt, mode, bar);
+        output.bar.set_style(spinner_style());
+        if let Some(len) = len {
+            output.set_progress(0, len);
+        }
+        output
+    }
+
+    /// Only the messages, printed on stderr
+    pub fn hidden() -> Self {
+        Output {
+            bar: ProgressBar::hidden(),
+            mode: Mode::Quiet,
+            length: Default::default(),
+            style: Default::default(),
+            prefix: String::new(),
+            log_interval: Duration::ZERO,
+            tick_interval: Duration::ZERO,
+            last_status_line: Default::default(),
+            timestamp_format: String::new(),
+            utc: false,
+            attempt: Default::default(),
+            cluster: None,
+            operation: Operation::Delete,
+            on_progress: None,
+        }
+    }
+
+    /// Output of a library job: nothing printed, the progress reported to `on_progress`
+    pub fn callback(opt: &Opt, on_progress: Option<ProgressCallback>) -> Self {
+        Output {
+            on_progress,
+            ..Self::with_bar(opt, Mode::Silent, ProgressBar::hidden())
+        }
+    }
+
+    fn mode(opt: &Opt) -> Mode {
+        if opt.quiet {
+            Mode::Silent
+        } else if opt.progress == ProgressFormat::Jsonl {
+            Mode::Quiet
+        } else if opt.no_progress || Terminal::stderr() != Terminal::Ansi {
+            Mode::Lines
+        } else {
+            Mode::Bar
+        }
+    }
+
+    fn with_bar(opt: &Opt, mode: Mode, bar: ProgressBar) -> Self {
+        Output {
+            bar,
+            mode,
+            length: Default::default(),
+            style: Default::default(),
+            prefix: String::new(),
+            log_interval: Duration::from_secs(opt.log_interval_secs),
+            tick_interval: Duration::from_millis(opt.tick_interval_millis),
+            last_status_line: Default::default(),
+            timestamp_format: opt.timestamp_format.clone(),
+            utc: opt.utc,
+            attempt: Default::default(),
+            cluster: opt.cluster_label.clone(),
+            operation: Operation::of(opt),
+            on_progress: None,
+        }
+    }
+
+    /// Whether an animated progress bar is drawn, that needs ticking
+    pub fn is_animated(&self) -> bool {
+        self.mode == Mode::Bar
+    }
+
+    /// Style of the bar, once its length is known
+    pub fn set_style(&self, style: ProgressStyle) {
+        if self.length.load(Ordering::SeqCst) > 0 {
+            self.bar.set_style(style.clone());
+        }
+        *self
+            .style
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(style);
+    }
+
+    pub fn set_prefix(&mut self, prefix: &str) {
+        self.bar.set_prefix(format!("{} ", prefix));
+        self.prefix = format!("{}: ", prefix);
+    }
+
+    /// Redraw the spinner every --tick-interval from a thread of the bar, until the bar is
+    /// finished or the returned guard dropped, whichever comes first: the run returning on an
+    /// error does not leave it ticking
+    pub fn enable_steady_tick(&self) -> SteadyTick {
+        let ticking = self.is_animated() && !self.tick_interval.is_zero();
+        if ticking {
+            self.bar
+                .enable_steady_tick(self.tick_interval.as_millis() as u64);
+        }
+        SteadyTick(ticking.then(|| self.bar.clone()))
+    }
+
+    pub fn tick(&self) {
+        if self.is_animated() {
+            self.bar.tick();
+        }
+    }
+
+    /// Attempt of the running delete by query, 0 once it is over
+    pub fn set_attempt(&self, attempt: u32) {
+        self.attempt.store(attempt, Ordering::Relaxed);
+    }
+
+    /// Start of the printed lines, eg: [2024-05-03 02:14:55] [https://eu.example.com] [attempt 3]
+    fn stamp(&self) -> String {
+        let mut stamp = String::new();
+        if !self.timestamp_format.is_empty() {
+            stamp = format!(
+                "[{}] ",
+                DateTime::now(self.utc).format(&self.timestamp_format)
+            );
+        }
+        if let Some(cluster) = &self.cluster {
+            stamp.push_str(&format!("[{}] ", cluster));
+        }
+        let attempt = self.attempt.load(Ordering::Relaxed);
+        if attempt > 1 {
+            stamp.push_str(&format!("[attempt {}] ", attempt));
+        }
+        stamp
+    }
+
+    /// Print a timestamped line above the progress bar, or on stderr
+    pub fn println(&self, line: impl AsRef<str>) {
+        if self.mode != Mode::Silent {
+            self.log(format!("{}{}", self.stamp(), line.as_ref()));
+        }
+    }
+
+    /// Print a log line above the progress bar, or on stderr, even with --quiet
+    fn log(&self, line: impl AsRef<str>) {
+        match self.mode {
+            Mode::Bar => self.bar.println(line),
+            Mode::Lines | Mode::Quiet | Mode::Silent => eprintln!("{}", line.as_ref()),
+        }
+    }
+
+    /// Transient message of the progress bar (eg: a countdown), not shown without a bar
+    pub fn set_message(&self, message: impl Into<String>) {
+        if self.is_animated() {
+            self.bar.set_message(message.into());
+        }
+    }
+
+    /// Message of the progress bar, printed on stderr without a bar
+    pub fn notify(&self, message: impl Into<String>) {
+        match self.mode {
+            Mode::Bar => self.bar.set_message(message.into()),
+            Mode::Lines | Mode::Quiet => {
+                eprintln!("{}{}{}", self.stamp(), self.prefix, message.into())
+            }
+            Mode::Silent => {}
+        }
+    }
+
+    /// The length only grows: documents indexed after the count, or another attempt, make it
+    /// larger, a task that does not report its total yet does not make it shrink
+    pub fn set_progress(&self, position: u64, length: u64) {
+        let previous = self.length.fetch_max(length, Ordering::SeqCst);
+        if previous == 0 && length > 0 {
+            let style = self
+                .style
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            if let Some(style) = style {
+                self.bar.set_style(style);
+            }
+        }
+        self.bar.set_length(previous.max(length));
+        self.bar.set_position(position);
+    }
+
+    pub fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    /// Estimated time left, from the progress so far
+    pub fn eta(&self) -> Duration {
+        self.bar.eta()
+    }
+
+    /// Progress of the running task: `details` and `estimate` in the bar message, or a status
+    /// line at most every --log-interval, eg:
+    /// deleted 1,234,567 / 9,876,543 (12%), conflicts 3, batch 210, ≈ 1.2k docs/s, ETA 3h40m
+    /// The progress bar message of a running task, eg: Delete in progress, attempt 2 (batch 3 • …)
+    pub fn status_message(&self, details: &str, estimate: &str) {
+        if self.mode != Mode::Bar {
+            return;
+        }
+        let attempt = self.attempt.load(Ordering::Relaxed);
+        let attempt = if attempt > 1 {
+            format!(", attempt {}", attempt)
+        } else {
+            String::new()
+        };
+        self.bar.set_message(format!(
+            "{} in progress{} ({}) {}",
+            self.operation.title(),
+            attempt,
+            details,
+            estimate
+        ));
+    }
+
+    pub fn task_status(
+        &self,
+        task_id: &TaskId,
+        position: u64,
+        length: u64,
+        status: &TaskStatus,
+        details: &str,
+        estimate: &str,
+    ) {
+        self.set_progress(position, length);
+        let length = self.length.load(Ordering::SeqCst);
+        crate::metrics::record(
+            self.cluster.as_deref(),
+            length,
+            position,
+            status,
+            self.attempt.load(Ordering::Relaxed),
+        );
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(&Progress {
+                task_id: task_id.clone(),
+                attempt: self.attempt.load(Ordering::Relaxed),
+                total: length,
+                deleted: position,
+                version_conflicts: status.version_conflicts.max(0) as u64,
+                batches: status.batches.max(0) as u64,
+            });
+        }
+        if status.total() <= 0 {
+            return;
+        }
+        match self.mode {
+            Mode::Bar => self.status_message(details, estimate),
+            Mode::Lines if self.status_line_due() => {
+                let mut line = format!(
+                    "{}{}{} {} / {} ({}%), conflicts {}, batch {}",
+                    self.stamp(),
+                    self.prefix,
+                    self.operation.done(),
+                    format_thousands(position),
+                    format_thousands(length),
+                    (position * 100).checked_div(length).unwrap_or(0),
+                    status.version_conflicts,
+                    status.batches
+                );
+                if !estimate.is_empty() {
+                    line.push_str(", ");
+                    line.push_str(estimate);
+                }
+                eprintln!("{}", line);
+            }
+            Mode::Lines | Mode::Quiet | Mode::Silent => {}
+        }
+    }
+
+    fn status_line_due(&self) -> bool {
+        let mut last = self
+            .last_status_line
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        if last.is_some_and(|last| now.duration_since(last) < self.log_interval) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+
+    pub fn abandon(&self) {
+        self.bar.abandon();
+    }
+
+    pub fn abandon_with_message(&self, message: impl Into<String>) {
+        match self.mode {
+            Mode::Bar => self.bar.abandon_with_message(message.into()),
+            Mode::Lines | Mode::Quiet => {
+                eprintln!("{}{}{}", self.stamp(), self.prefix, message.into())
+            }
+            Mode::Silent => {}
+        }
+    }
+
+    pub fn finish_with_message(&self, message: impl Into<String>) {
+        match self.mode {
+            Mode::Bar => self.bar.finish_with_message(message.into()),
+            Mode::Lines | Mode::Quiet => {
+                eprintln!("{}{}{}", self.stamp(), self.prefix, message.into())
+            }
+            Mode::Silent => {}
+        }
+    }
+
+    pub fn finish_at_current_pos(&self) {
+        self.bar.finish_at_current_pos();
+    }
+}
+
+/// Stops the steady tick of a bar when dropped
+#[must_use]
+pub struct SteadyTick(Option<ProgressBar>);
+
+impl Drop for SteadyTick {
+    fn drop(&mut self) {
+        // disabling ticks the bar once: never over a finished bar, and what was printed after it
+        if let Some(bar) = self.0.take().filter(|bar| !bar.is_finished()) {
+            bar.disable_steady_tick();
+        }
+    }
+}
+
+/// Style of a bar whose length is not known yet
+fn spinner_style() -> ProgressStyle {
+    ProgressStyle::default_spinner().template("{prefix}{spinner} [{elapsed_precise}] {msg}")
+}
+
+/// Output of the run the log lines are printed through, once it has a progress bar
+static LOG_OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+pub fn set_log_output(output: &Output) {
+    *LOG_OUTPUT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(output.clone());
+}
+
+/// Writer of the tracing logs, each log line is printed at once when the writer is dropped so
+/// that the progress bar redraws do not shred it
+#[derive(Default)]
+pub struct LogWriter(Vec<u8>);
+
+pub fn log_writer() -> LogWriter {
+    LogWriter::default()
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.0);
+        let line = line.trim_end_matches('\n');
+        match &*LOG_OUTPUT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+        {
+            Some(output) => output.log(line),
+            None => eprintln!("{}", line),
+        }
+    }
+}
+
+/// eg: 1,234,567
+pub fn format_thousands(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
@@ -0,0 +1,100 @@
+//! --plan: several deletes run one after the other, one job per line of an NDJSON file, eg:
+//!
+//! ```text
+//! {"index":"logs-app-*","query":{"range":{"@timestamp":{"lt":"now-30d"}}},"requests_per_second":200}
+//! {"index":"audit-*","query_string":"type:debug","max_docs":1000000}
+//! ```
+//!
+//! The options of a line replace the ones of the command line for its job, blank lines and lines
+//! starting with # are ignored.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{index_expression, parse_requests_per_second, Opt};
+
+/// A line of the plan
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Line number in the plan, to report the result of the job
+    #[serde(skip)]
+    pub line: usize,
+    /// Index pattern, or comma separated patterns
+    index: Option<String>,
+    /// JSON query
+    query: Option<serde_json::Value>,
+    /// Lucene query string, instead of the JSON query
+    query_string: Option<String>,
+    /// -1 for unlimited
+    requests_per_second: Option<f64>,
+    max_docs: Option<u64>,
+}
+
+impl Job {
+    /// The options of the job: the ones of the command line, replaced by the ones of its line
+    pub fn apply(&self, opt: &mut Opt) {
+        if let Some(index) = &self.index {
+            opt.index = index_expression(std::slice::from_ref(index), &opt.exclude);
+        }
+        if self.query.is_some() || self.query_string.is_some() {
+            opt.query = self.query.as_ref().map(|query| query.to_string());
+            opt.query_string = self.query_string.clone();
+            opt.older_than = None;
+            opt.ids_file = None;
+        }
+        if self.requests_per_second.is_some() {
+            opt.requests_per_second = self.requests_per_second;
+        }
+        if self.max_docs.is_some() {
+            opt.max_docs = self.max_docs;
+        }
+    }
+}
+
+/// The jobs of the plan, checked before the first one runs
+pub fn read(path: &Path, opt: &Opt) -> anyhow::Result<Vec<Job>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Unable to read the plan {}: {}", path.display(), e))?;
+    let has_query = opt.query.is_some()
+        || opt.query_string.is_some()
+        || opt.older_than.is_some()
+        || opt.ids_file.is_some();
+    let mut jobs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| {
+            anyhow::anyhow!(
+                "Invalid job on line {} of {}: {}",
+                number + 1,
+                path.display(),
+                reason
+            )
+        };
+        let mut job = serde_json::from_str::<Job>(line).map_err(|e| invalid(e.to_string()))?;
+        job.line = number + 1;
+        if job.query.is_some() && job.query_string.is_some() {
+            return Err(invalid(
+                "query and query_string cannot be used together".into(),
+            ));
+        }
+        if job.query.is_none() && job.query_string.is_none() && !has_query {
+            return Err(invalid(
+                "no query, and none given on the command line".into(),
+            ));
+        }
+        if let Some(requests_per_second) = job.requests_per_second {
+            parse_requests_per_second(&requests_per_second.to_string())
+                .map_err(|e| invalid(format!("requests_per_second: {}", e)))?;
+        }
+        jobs.push(job);
+    }
+    if jobs.is_empty() {
+        anyhow::bail!("No job in the plan {}", path.display());
+    }
+    Ok(jobs)
+}
@@ -0,0 +1,461 @@
+//! Checks run before submitting the delete by query, shared by the dry-run, preview and delete
+//! paths.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    append_index_options,
+    cluster::{self, Health},
+    error_for_status,
+    failures::IndexBlock,
+    index_url, json_body,
+    output::format_thousands,
+    segments_url, EsClient, Opt,
+};
+
+/// More targeted indices than this are summarized by the size table, unless --show-all-indices
+const SIZE_TABLE_MAX_ROWS: usize = 50;
+/// Number of the largest indices listed when the size table is summarized
+const SIZE_TABLE_LARGEST: usize = 10;
+
+/// A concrete index (or data stream) targeted by the index expression
+pub struct TargetIndex {
+    pub name: String,
+    /// Alias through which the index is targeted, when it is not matched directly
+    pub via_alias: Option<String>,
+    /// The alias is the write alias of this index
+    pub write_alias: bool,
+    /// Hidden or system index, data stream or one of its backing indices
+    pub protected: bool,
+}
+
+/// Resolve the index expression to the concrete indices it targets, with `_resolve/index`, or
+/// `/{index}/_alias` on clusters older than 7.9.
+pub async fn resolve_targets(opt: &Opt, client: &EsClient) -> anyhow::Result<Vec<TargetIndex>> {
+    let mut targets = match resolve_index(opt, client).await {
+        Ok(targets) => targets,
+        Err(resolve_error) => resolve_aliases(opt, client).await.map_err(|e| {
+            anyhow::anyhow!(
+                "_resolve/index failed ({}), then _alias failed: {}",
+                resolve_error,
+                e
+            )
+        })?,
+    };
+    let aliases = targets
+        .values()
+        .filter_map(|target| target.via_alias.as_deref())
+        .collect::<BTreeSet<_>>();
+    if !aliases.is_empty() {
+        // best effort: only used to flag the write aliases
+        let aliases = aliases.into_iter().collect::<Vec<_>>().join(",");
+        if let Ok(indices) = get_aliases(opt, client, &aliases).await {
+            for (name, target) in targets.iter_mut() {
+                target.write_alias = target.via_alias.as_ref().is_some_and(|alias| {
+                    indices
+                        .get(name)
+                        .and_then(|index| index.aliases.get(alias))
+                        .is_some_and(|alias| alias.is_write_index == Some(true))
+                });
+            }
+        }
+    }
+    Ok(targets.into_values().collect())
+}
+
+async fn resolve_index(
+    opt: &Opt,
+    client: &EsClient,
+) -> anyhow::Result<BTreeMap<String, TargetIndex>> {
+    let mut url = segments_url(&opt.url, ["_resolve", "index", opt.index.as_str()])?;
+    if let Some(expand_wildcards) = &opt.expand_wildcards {
+        url.query_pairs_mut()
+            .append_pair("expand_wildcards", expand_wildcards);
+    }
+    let response = json_body::<ResolveIndexResponse>(
+        error_for_status(client.send(client.get(url)).await?).await?,
+    )
+    .await?;
+    let mut targets = BTreeMap::new();
+    for index in response.indices {
+        let mut target = target(index.name.clone(), None);
+        target.protected |= index.data_stream.is_some()
+            || index
+                .attributes
+                .iter()
+                .any(|attribute| attribute == "hidden" || attribute == "system");
+        targets.insert(index.name, target);
+    }
+    for stream in response.data_streams {
+        let mut target = target(stream.name.clone(), None);
+        target.protected = true;
+        targets.insert(stream.name, target);
+    }
+    for ResolvedAlias {
+        name: alias,
+        indices,
+    } in response.aliases
+    {
+        for name in indices {
+            targets
+                .entry(name.clone())
+                .or_insert_with(|| target(name, Some(alias.clone())));
+        }
+    }
+    Ok(targets)
+}
+
+async fn resolve_aliases(
+    opt: &Opt,
+    client: &EsClient,
+) -> anyhow::Result<BTreeMap<String, TargetIndex>> {
+    let url = segments_url(&opt.url, [opt.index.as_str(), "_alias"])?;
+    let indices = json_body::<HashMap<String, IndexAliases>>(
+        error_for_status(client.send(client.get(url)).await?).await?,
+    )
+    .await?;
+    // an index is reached through an alias when the alias is named in the expression
+    let names = opt.index.split(',').collect::<Vec<_>>();
+    Ok(indices
+        .into_iter()
+        .map(|(name, index)| {
+            let via_alias = index
+                .aliases
+                .iter()
+                .find(|(alias, _)| names.contains(&alias.as_str()))
+                .map(|(alias, properties)| (alias.clone(), properties.is_write_index));
+            let mut target = target(name.clone(), via_alias.as_ref().map(|a| a.0.clone()));
+            target.write_alias = via_alias.is_some_and(|a| a.1 == Some(true));
+            (name, target)
+        })
+        .collect())
+}
+
+async fn get_aliases(
+    opt: &Opt,
+    client: &EsClient,
+    aliases: &str,
+) -> anyhow::Result<HashMap<String, IndexAliases>> {
+    let url = segments_url(&opt.url, ["_alias", aliases])?;
+    json_body(error_for_status(client.send(client.get(url)).await?).await?).await
+}
+
+fn target(name: String, via_alias: Option<String>) -> TargetIndex {
+    TargetIndex {
+        // the older clusters do not flag the hidden indices, named with a leading dot
+        protected: name.starts_with('.'),
+        name,
+        via_alias,
+        write_alias: false,
+    }
+}
+
+pub fn print_targets(targets: &[TargetIndex]) {
+    info!("Target indices ({}):", targets.len());
+    for target in targets {
+        match &target.via_alias {
+            Some(alias) if target.write_alias => {
+                info!("  {} (via alias {}, WRITE ALIAS)", target.name, alias)
+            }
+            Some(alias) => info!("  {} (via alias {})", target.name, alias),
+            None => info!("  {}", target.name),
+        }
+    }
+}
+
+/// Health, document count and store size of each targeted index, with the totals, from
+/// `_cat/indices`. Only warns when it is not allowed or fails, the size is informative.
+pub async fn print_sizes(opt: &Opt, client: &EsClient) {
+    let mut indices = match cat_indices(opt, client, &opt.index).await {
+        Ok(indices) => indices,
+        Err(e) => {
+            eprintln!(
+                "Warning: unable to get the size of the target indices: {}",
+                e
+            );
+            return;
+        }
+    };
+    if indices.is_empty() {
+        return;
+    }
+    let count = |value: &Option<String>| value.as_deref().and_then(|v| v.parse::<u64>().ok());
+    let docs = indices
+        .iter()
+        .filter_map(|i| count(&i.docs_count))
+        .sum::<u64>();
+    let bytes = indices
+        .iter()
+        .filter_map(|i| count(&i.store_size))
+        .sum::<u64>();
+    let total = indices.len();
+    let summarized = total > SIZE_TABLE_MAX_ROWS && !opt.show_all_indices;
+    if summarized {
+        indices.sort_by_key(|index| std::cmp::Reverse(count(&index.store_size)));
+        indices.truncate(SIZE_TABLE_LARGEST);
+    } else {
+        indices.sort_by(|a, b| a.index.cmp(&b.index));
+    }
+    let rows = indices
+        .iter()
+        .map(|index| {
+            [
+                index.health.clone().unwrap_or_else(|| "-".into()),
+                count(&index.docs_count).map_or_else(|| "-".into(), format_thousands),
+                count(&index.store_size).map_or_else(|| "-".into(), format_bytes),
+                index.index.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let width = |column: usize, header: &str| {
+        rows.iter()
+            .map(|row| row[column].len())
+            .chain(std::iter::once(header.len()))
+            .max()
+            .unwrap_or_default()
+    };
+    let (health_width, docs_width, size_width) =
+        (width(0, "health"), width(1, "docs"), width(2, "size"));
+    if summarized {
+        eprintln!(
+            "{} indices targeted, the {} largest (--show-all-indices to list them all):",
+            total, SIZE_TABLE_LARGEST
+        );
+    }
+    eprintln!(
+        "{:<hw$}  {:>dw$}  {:>sw$}  index",
+        "health",
+        "docs",
+        "size",
+        hw = health_width,
+        dw = docs_width,
+        sw = size_width
+    );
+    for [health, docs, size, index] in &rows {
+        eprintln!(
+            "{:<hw$}  {:>dw$}  {:>sw$}  {}",
+            health,
+            docs,
+            size,
+            index,
+            hw = health_width,
+            dw = docs_width,
+            sw = size_width
+        );
+    }
+    eprintln!(
+        "{:<hw$}  {:>dw$}  {:>sw$}  {} indices",
+        "total",
+        format_thousands(docs),
+        format_bytes(bytes),
+        total,
+        hw = health_width,
+        dw = docs_width,
+        sw = size_width
+    );
+}
+
+/// eg: 512b, 1.5kb, 12.3gb (binary multiples, as ES prints them)
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["kb", "mb", "gb", "tb", "pb"];
+    if bytes < 1024 {
+        return format!("{}b", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+/// The `_cat/indices` rows of the index expression
+pub async fn cat_indices(
+    opt: &Opt,
+    client: &EsClient,
+    expression: &str,
+) -> anyhow::Result<Vec<CatIndex>> {
+    let mut url = segments_url(&opt.url, ["_cat", "indices", expression])?;
+    {
+        let mut params = url.query_pairs_mut();
+        params.append_pair("format", "json");
+        params.append_pair("bytes", "b");
+        if let Some(expand_wildcards) = &opt.expand_wildcards {
+            params.append_pair("expand_wildcards", expand_wildcards);
+        }
+    }
+    json_body(error_for_status(client.send(client.get(url)).await?).await?).await
+}
+
+/// --preflight-checks: the cluster state that would make the delete by query fail or hurt the
+/// cluster. Every problem is printed, false when one of them must stop the deletion.
+pub async fn run_checks(opt: &Opt, client: &EsClient) -> bool {
+    let mut passed = true;
+    match cluster::health(opt, client).await {
+        Ok(Health::Red) if opt.force => {
+            eprintln!("Preflight: cluster health is red, deleting anyway (--force)")
+        }
+        Ok(Health::Red) => {
+            eprintln!(
+                "Preflight failed: cluster health is red, some primary shards are unassigned and \
+                 the delete by query would fail on them. Find out why with \
+                 GET _cluster/allocation/explain, or use --force to delete anyway"
+            );
+            passed = false;
+        }
+        Ok(health) => info!("Preflight: cluster health is {}", health),
+        Err(e) => eprintln!("Preflight warning: unable to get the cluster health: {}", e),
+    }
+    match allocation(opt, client).await {
+        Ok(nodes) => {
+            for node in nodes {
+                let percent = match node.disk_percent.as_deref().map(str::parse::<f64>) {
+                    Some(Ok(percent)) => percent,
+                    // the UNASSIGNED row, or a node without disk stats
+                    _ => continue,
+                };
+                if percent > opt.disk_usage_threshold {
+                    eprintln!(
+                        "Preflight warning: node {} uses {}% of its disk ({} available), more than \
+                         --disk-usage-threshold {}%: deleting documents writes new segments before \
+                         the old ones are merged away, free some space first or the flood-stage \
+                         watermark may turn the indices read-only",
+                        node.node.as_deref().unwrap_or("?"),
+                        percent,
+                        node.disk_avail.as_deref().unwrap_or("?"),
+                        opt.disk_usage_threshold
+                    );
+                } else {
+                    info!(
+                        "Preflight: node {} uses {}% of its disk",
+                        node.node.as_deref().unwrap_or("?"),
+                        percent
+                    );
+                }
+            }
+        }
+        Err(e) => eprintln!("Preflight warning: unable to get the disk usage: {}", e),
+    }
+    match blocks(opt, client).await {
+        Ok(indices) => {
+            for (index, settings) in indices {
+                let blocked = READ_ONLY_BLOCKS
+                    .iter()
+                    .filter(|block| settings.settings.get(**block).is_some_and(is_true))
+                    .collect::<Vec<_>>();
+                for block in &blocked {
+                    eprintln!(
+                        "Preflight failed: the delete by query would fail with \
+                         cluster_block_exception, {}",
+                        IndexBlock {
+                            index: index.clone(),
+                            block,
+                        }
+                    );
+                }
+                passed &= blocked.is_empty();
+            }
+        }
+        Err(e) => eprintln!("Preflight warning: unable to get the index blocks: {}", e),
+    }
+    passed
+}
+
+/// Index settings that reject the document deletions
+const READ_ONLY_BLOCKS: [&str; 3] = [
+    "index.blocks.read_only",
+    "index.blocks.read_only_allow_delete",
+    "index.blocks.write",
+];
+
+/// The settings are strings, unless set from a JSON boolean on some versions
+fn is_true(value: &serde_json::Value) -> bool {
+    value == "true" || value == true
+}
+
+async fn allocation(opt: &Opt, client: &EsClient) -> anyhow::Result<Vec<NodeAllocation>> {
+    let mut url = segments_url(&opt.url, ["_cat", "allocation"])?;
+    url.query_pairs_mut().append_pair("format", "json");
+    json_body(error_for_status(client.send(client.get(url)).await?).await?).await
+}
+
+/// The block settings of the targeted indices
+async fn blocks(opt: &Opt, client: &EsClient) -> anyhow::Result<BTreeMap<String, IndexSettings>> {
+    let mut url = index_url(opt, "_settings/index.blocks.*")?;
+    {
+        let mut params = url.query_pairs_mut();
+        params.append_pair("flat_settings", "true");
+        append_index_options(opt, &mut params);
+    }
+    json_body(error_for_status(client.send(client.get(url)).await?).await?).await
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeAllocation {
+    node: Option<String>,
+    #[serde(rename = "disk.percent")]
+    disk_percent: Option<String>,
+    #[serde(rename = "disk.avail")]
+    disk_avail: Option<String>,
+}
+
+/// A row of `_cat/indices`, the counts are strings and missing for closed indices
+#[derive(Deserialize, Debug)]
+pub struct CatIndex {
+    health: Option<String>,
+    pub index: String,
+    #[serde(rename = "docs.count")]
+    pub docs_count: Option<String>,
+    #[serde(rename = "store.size")]
+    store_size: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IndexSettings {
+    #[serde(default)]
+    settings: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResolveIndexResponse {
+    #[serde(default)]
+    indices: Vec<ResolvedIndex>,
+    #[serde(default)]
+    aliases: Vec<ResolvedAlias>,
+    #[serde(default)]
+    data_streams: Vec<ResolvedDataStream>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResolvedIndex {
+    name: String,
+    #[serde(default)]
+    attributes: Vec<String>,
+    /// Data stream of a backing index
+    data_stream: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResolvedAlias {
+    name: String,
+    indices: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResolvedDataStream {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IndexAliases {
+    aliases: HashMap<String, AliasProperties>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AliasProperties {
+    is_write_index: Option<bool>,
+}
@@ -0,0 +1,146 @@
+//! --throttle-schedule: requests per second by time of day.
+
+use crate::clock::DateTime;
+
+/// Time windows of the day, in local time, each with its requests per second
+#[derive(Clone, Debug)]
+pub struct ThrottleSchedule {
+    windows: Vec<ThrottleWindow>,
+}
+
+#[derive(Clone, Debug)]
+struct ThrottleWindow {
+    /// Minutes since midnight, the window ends before `end` and wraps around midnight when
+    /// `end <= start`
+    start: u32,
+    end: u32,
+    requests_per_second: f64,
+}
+
+impl ThrottleSchedule {
+    /// Parse a schedule like `08:00-20:00=50,20:00-08:00=500`
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let windows = value
+            .split(',')
+            .map(|window| {
+                let (range, rate) = window.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("expected a window like 08:00-20:00=50, got {:?}", window)
+                })?;
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or_else(|| anyhow::anyhow!("expected a range like 08:00-20:00"))?;
+                Ok(ThrottleWindow {
+                    start: parse_time_of_day(start)?,
+                    end: parse_time_of_day(end)?,
+                    requests_per_second: crate::parse_requests_per_second(rate.trim())?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(ThrottleSchedule { windows })
+    }
+
+    /// Requests per second of the first window containing the time of day of `now`, in local
+    /// time (see `DateTime::now`)
+    pub fn current_rate(&self, now: &DateTime) -> Option<f64> {
+        let minute = now.hour * 60 + now.minute;
+        self.windows
+            .iter()
+            .find(|window| {
+                if window.start < window.end {
+                    window.start <= minute && minute < window.end
+                } else {
+                    minute >= window.start || minute < window.end
+                }
+            })
+            .map(|window| window.requests_per_second)
+    }
+}
+
+/// As parsed, eg: 08:00-20:00=50,20:00-08:00=500
+impl std::fmt::Display for ThrottleSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, window) in self.windows.iter().enumerate() {
+            write!(
+                f,
+                "{}{:02}:{:02}-{:02}:{:02}={}",
+                if i == 0 { "" } else { "," },
+                window.start / 60,
+                window.start % 60,
+                window.end / 60,
+                window.end % 60,
+                window.requests_per_second
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_time_of_day(value: &str) -> anyhow::Result<u32> {
+    let value = value.trim();
+    let (hours, minutes) = value.split_once(':').unwrap_or((value, "0"));
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => anyhow::bail!("expected a time of day like 08:00, got {:?}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThrottleSchedule;
+    use crate::clock::DateTime;
+
+    fn at(time: &str) -> DateTime {
+        let (hour, minute) = time.split_once(':').unwrap();
+        DateTime {
+            year: 2024,
+            month: 3,
+            day: 1,
+            hour: hour.parse().unwrap(),
+            minute: minute.parse().unwrap(),
+            second: 30,
+        }
+    }
+
+    #[test]
+    fn parses_the_schedule() {
+        let schedule = ThrottleSchedule::parse("08:00-20:00=50, 20:00-8=unlimited").unwrap();
+        assert_eq!(schedule.to_string(), "08:00-20:00=50,20:00-08:00=-1");
+        for invalid in [
+            "",
+            "08:00-20:00",
+            "08:00=50",
+            "08:00-24:00=50",
+            "08:60-20:00=50",
+            "08:00-20:00=0",
+            "08:00-20:00=fast",
+        ] {
+            assert!(ThrottleSchedule::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn takes_the_rate_of_the_window_of_the_time_of_day() {
+        let schedule = ThrottleSchedule::parse("08:00-20:00=50,20:00-08:00=500").unwrap();
+        let rate = |time| schedule.current_rate(&at(time));
+        assert_eq!(rate("08:00"), Some(50.0));
+        assert_eq!(rate("19:59"), Some(50.0));
+        // the window wrapping midnight
+        assert_eq!(rate("20:00"), Some(500.0));
+        assert_eq!(rate("23:59"), Some(500.0));
+        assert_eq!(rate("00:00"), Some(500.0));
+        assert_eq!(rate("07:59"), Some(500.0));
+    }
+
+    #[test]
+    fn has_no_rate_outside_of_the_windows() {
+        let schedule = ThrottleSchedule::parse("22:00-06:00=500,12:00-13:00=100").unwrap();
+        let rate = |time| schedule.current_rate(&at(time));
+        assert_eq!(rate("06:00"), None);
+        assert_eq!(rate("12:30"), Some(100.0));
+        assert_eq!(rate("13:00"), None);
+        assert_eq!(rate("03:00"), Some(500.0));
+        // the first window containing the time wins
+        let schedule = ThrottleSchedule::parse("00:00-00:00=10,08:00-20:00=50").unwrap();
+        assert_eq!(schedule.current_rate(&at("12:00")), Some(10.0));
+    }
+}
@@ -0,0 +1,281 @@
+//! --state-file: the task of a delete and the documents it deleted so far, recorded after each
+//! submission and each completed attempt, so that a run restarted after a crash (or a closed SSH
+//! session) re-attaches to the task still running instead of submitting an overlapping delete.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    confirm, display_url, get_task, is_not_found, lock, EsClient, Opt, RunSummary, TaskId,
+};
+
+/// Content of the state file
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunState {
+    url: String,
+    index: String,
+    /// SHA-256 of the queries: the state of another query is never resumed
+    query_hash: String,
+    /// Task running, none between the attempts
+    task_id: Option<TaskId>,
+    /// Documents deleted by the completed attempts
+    deleted_total: u64,
+}
+
+/// The state file of a run, locked until the run ends
+pub struct StateFile {
+    path: PathBuf,
+    file: Mutex<(File, RunState)>,
+    /// Task of the previous run to re-attach to instead of submitting the first attempt
+    reattach: Mutex<Option<TaskId>>,
+}
+
+/// Open and lock the state file of the run, with the state left by a previous run. Runs of
+/// several tasks (--per-index, --chunk-window, several --ids-file batches) are not recorded.
+pub fn open(
+    opt: &Opt,
+    queries: &[serde_json::Value],
+) -> anyhow::Result<Option<(StateFile, Option<RunState>)>> {
+    if opt.no_state_file {
+        return Ok(None);
+    }
+    if opt.per_index || !opt.chunk_windows.is_empty() || queries.len() > 1 {
+        if opt.state_file.is_some() {
+            anyhow::bail!(
+                "--state-file records a single task, it cannot be used with --per-index, --chunk-window or several --ids-file batches"
+            );
+        }
+        return Ok(None);
+    }
+    let state = RunState {
+        url: display_url(&opt.url).to_string(),
+        index: opt.index.clone(),
+        query_hash: sha256(
+            &queries
+                .iter()
+                .map(|query| query.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        task_id: None,
+        deleted_total: 0,
+    };
+    let path = match &opt.state_file {
+        Some(path) => path.clone(),
+        None => match default_path(&state) {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+    let file = match open_locked(&path) {
+        Ok(file) => file,
+        // the default state file is a convenience, the delete runs without it
+        Err(e) if opt.state_file.is_none() && !e.to_string().contains("locked") => {
+            eprintln!("Warning: {}, the run is not recorded", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    let mut text = String::new();
+    (&file)
+        .read_to_string(&mut text)
+        .map_err(|e| anyhow::anyhow!("Unable to read the state file {}: {}", path.display(), e))?;
+    let previous = match serde_json::from_str::<RunState>(&text) {
+        Ok(previous) => Some(previous),
+        Err(_) if text.trim().is_empty() => None,
+        Err(e) => {
+            eprintln!(
+                "Warning: ignoring the invalid state file {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    };
+    let state_file = StateFile {
+        path,
+        file: Mutex::new((file, state)),
+        reattach: Mutex::new(None),
+    };
+    Ok(Some((state_file, previous)))
+}
+
+/// ~/.cache/es-delete-by-query/state-<hash>.json, or under $XDG_CACHE_HOME, one file per
+/// cluster, index and query
+fn default_path(state: &RunState) -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    let key = sha256(&format!(
+        "{}\n{}\n{}",
+        state.url, state.index, state.query_hash
+    ));
+    Some(
+        cache_home
+            .join("es-delete-by-query")
+            .join(format!("state-{}.json", &key[..16])),
+    )
+}
+
+fn open_locked(path: &Path) -> anyhow::Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            anyhow::anyhow!("Unable to create the directory {}: {}", parent.display(), e)
+        })?;
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Unable to open the state file {}: {}", path.display(), e))?;
+    if !try_lock(&file) {
+        anyhow::bail!(
+            "The state file {} is locked by another run of this delete",
+            path.display()
+        );
+    }
+    Ok(file)
+}
+
+/// Exclusive advisory lock, released when the file is closed
+#[cfg(unix)]
+fn try_lock(file: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: flock only operates on the file descriptor, owned by `file`
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+/// No portable advisory lock, concurrent runs are not detected
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> bool {
+    true
+}
+
+fn sha256(text: &str) -> String {
+    digest::digest(&digest::SHA256, text.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl StateFile {
+    /// Take over the state of the previous run of the same delete: its task still running is
+    /// re-attached to (with --resume, or once confirmed), the documents it deleted are accounted.
+    /// Returns false when the re-attachment is declined.
+    pub async fn resume(
+        &self,
+        previous: RunState,
+        opt: &Opt,
+        client: &EsClient,
+        summary: &Mutex<RunSummary>,
+    ) -> anyhow::Result<bool> {
+        {
+            let (_, state) = &*lock(&self.file);
+            if (&previous.url, &previous.index, &previous.query_hash)
+                != (&state.url, &state.index, &state.query_hash)
+            {
+                eprintln!(
+                    "Ignoring the state of another delete left in {}",
+                    self.path.display()
+                );
+                return Ok(true);
+            }
+        }
+        let mut deleted = previous.deleted_total;
+        match &previous.task_id {
+            Some(task_id) => match get_task(task_id, opt, client).await {
+                Ok(response) if !response.completed => {
+                    eprintln!(
+                        "Task {} of a previous run of this delete is still running",
+                        task_id.0
+                    );
+                    let reattach = opt.resume
+                        || (atty::is(atty::Stream::Stdin)
+                            && confirm("Re-attach to it instead of submitting a new delete? type 'yes' to re-attach: ")?);
+                    if !reattach {
+                        eprintln!(
+                            "Not submitting an overlapping delete: re-attach to the task with --resume, or stop it with `cancel {}`",
+                            task_id.0
+                        );
+                        return Ok(false);
+                    }
+                    *lock(&self.reattach) = Some(task_id.clone());
+                }
+                Ok(response) => {
+                    deleted += response.task.status.counters().processed().max(0) as u64;
+                    eprintln!("Task {} of a previous run completed", task_id.0);
+                }
+                Err(e) if is_not_found(&e) => {
+                    eprintln!("Task {} of a previous run is no longer known", task_id.0)
+                }
+                Err(e) => anyhow::bail!(
+                    "Unable to check the task {} of the previous run recorded in {}: {}",
+                    task_id.0,
+                    self.path.display(),
+                    e
+                ),
+            },
+            None => eprintln!("Resuming an interrupted run of this delete"),
+        }
+        if deleted > 0 {
+            eprintln!("{} documents deleted by the previous run", deleted);
+            lock(summary).deleted += deleted;
+        }
+        Ok(true)
+    }
+
+    pub fn has_reattach(&self) -> bool {
+        lock(&self.reattach).is_some()
+    }
+
+    /// The task of the previous run to wait for instead of submitting a new one, once
+    pub fn take_reattach(&self) -> Option<TaskId> {
+        lock(&self.reattach).take()
+    }
+
+    /// Record the running task (none once it completed) and the documents deleted so far
+    pub fn record(&self, task_id: Option<&TaskId>, deleted_total: u64) {
+        let (file, state) = &mut *lock(&self.file);
+        state.task_id = task_id.cloned();
+        state.deleted_total = deleted_total;
+        let written = serde_json::to_vec(state)
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&json)?;
+                file.sync_data()
+            });
+        if let Err(e) = written {
+            eprintln!(
+                "Warning: unable to write the state file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// The delete completed, a new run starts afresh
+    pub fn remove(self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            eprintln!(
+                "Warning: unable to remove the state file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
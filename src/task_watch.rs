@@ -0,0 +1,120 @@
+//! `task watch`: the progress of any running task, eg: a reindex started by another tool. The
+//! bulk by scroll tasks (reindex, update and delete by query) report counters shown in a progress
+//! bar, the other tasks only their raw status.
+
+use std::time::Duration;
+
+use indicatif::ProgressStyle;
+use tokio::time::sleep;
+
+use crate::{
+    detach_on_interrupt, failures, format_duration, get_task, is_not_found, output::Output,
+    progress_style, status_line, AnyTaskStatus, CliError, EsClient, GetTaskResponse, Opt, TaskId,
+};
+
+/// Longer raw statuses are truncated in the spinner message
+const RAW_STATUS_MAX_CHARS: usize = 200;
+
+pub async fn watch(
+    opt: &Opt,
+    task_id: &TaskId,
+    cancel_on_interrupt: bool,
+    client: &EsClient,
+) -> anyhow::Result<()> {
+    let mut response = match get_task(task_id, opt, client).await {
+        Err(e) if is_not_found(&e) => {
+            return Err(CliError::TaskNotFound(anyhow::anyhow!(
+                "Task {} not found: {}",
+                task_id.0,
+                e
+            ))
+            .into());
+        }
+        response => response?,
+    };
+    let output = Output::new(opt, None);
+    match response.task.status {
+        AnyTaskStatus::Known(_) => output.set_style(progress_style()),
+        AnyTaskStatus::Other(_) => output.set_style(
+            ProgressStyle::default_spinner().template("{spinner} [{elapsed_precise}] {msg}"),
+        ),
+    }
+    let _ticking = output.enable_steady_tick();
+    let detached = detach_on_interrupt(
+        &output,
+        opt,
+        client,
+        task_id,
+        response.task.cancellable,
+        cancel_on_interrupt,
+    )?;
+    output.println(format!(
+        "Watching task {}: {} {}",
+        task_id.0, response.task.action, response.task.description
+    ));
+    let watching = async {
+        while !response.completed {
+            show(&output, &response);
+            sleep(Duration::from_secs(opt.poll_interval_secs.max(1))).await;
+            match get_task(task_id, opt, client).await {
+                Ok(next) => response = next,
+                Err(e) => output.println(format!("Unable to get the task: {}", e)),
+            }
+        }
+    };
+    tokio::select! {
+        _ = watching => {}
+        Ok(result) = detached => return result,
+    }
+    let running_time = format_duration(Duration::from_nanos(
+        response.task.running_time_in_nanos as u64,
+    ));
+    if let Some(error) = &response.error {
+        output.abandon_with_message("Task failed.");
+        anyhow::bail!("task {} failed: {}", task_id.0, error);
+    }
+    match &response.response {
+        Some(result) if !result.failures.is_empty() => {
+            output.abandon_with_message("Task completed with failures.");
+            anyhow::bail!(
+                "task {} completed with failures:\n{}",
+                task_id.0,
+                failures::aggregated(&result.failures, opt)
+            );
+        }
+        _ => match &response.task.status {
+            AnyTaskStatus::Other(serde_json::Value::Null) => {
+                output.finish_with_message(format!("Task completed in {}", running_time))
+            }
+            status => output
+                .finish_with_message(format!("Task completed in {}: {}", running_time, status)),
+        },
+    }
+    Ok(())
+}
+
+fn show(output: &Output, response: &GetTaskResponse) {
+    let running_time = Duration::from_nanos(response.task.running_time_in_nanos as u64);
+    match &response.task.status {
+        AnyTaskStatus::Known(status) => {
+            output.set_progress(
+                status.processed().max(0) as u64,
+                status.total().max(0) as u64,
+            );
+            output.set_message(status_line(&response.task, Duration::ZERO));
+        }
+        AnyTaskStatus::Other(status) => {
+            let mut raw = status.to_string();
+            if raw.chars().count() > RAW_STATUS_MAX_CHARS {
+                raw = raw.chars().take(RAW_STATUS_MAX_CHARS).collect();
+                raw.push('…');
+            }
+            output.set_message(format!(
+                "{} running for {}: {}",
+                response.task.action,
+                format_duration(running_time),
+                raw
+            ));
+        }
+    }
+}
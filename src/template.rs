@@ -0,0 +1,159 @@
+//! --var: Mustache-style placeholders of the JSON query, filled before it is parsed. `{{name}}`
+//! is replaced by the value escaped as the content of a JSON string, `{{#json name}}` by the
+//! value as is (a number, a boolean, an object...), eg:
+//!
+//! ```text
+//! {"bool":{"filter":[{"range":{"{{field}}":{"lt":"{{cutoff}}"}}},{"terms":{"tenant":{{#json tenants}}}}]}}
+//! ```
+
+use std::collections::BTreeSet;
+
+/// `name=value` of --var
+pub fn parse_var(value: &str) -> anyhow::Result<(String, String)> {
+    match value.split_once('=') {
+        Some((name, value)) if is_name(name) => Ok((name.to_string(), value.to_string())),
+        _ => anyhow::bail!("expected name=value, the name made of letters, digits, _, - or ."),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The template with its placeholders replaced by the values of the variables, every
+/// placeholder must have a value
+pub fn render(template: &str, vars: &[(String, String)]) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut unresolved = BTreeSet::new();
+    let mut used = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => anyhow::bail!("Unclosed placeholder in the query: {}", &rest[start..]),
+        };
+        let tag = rest[start + 2..end].trim();
+        let (name, raw) = match tag.strip_prefix("#json") {
+            Some(name) => (name.trim(), true),
+            None => (tag, false),
+        };
+        if !is_name(name) {
+            anyhow::bail!(
+                "Invalid placeholder in the query: {}",
+                &rest[start..end + 2]
+            );
+        }
+        // the last --var of a name wins
+        match vars.iter().rev().find(|(var, _)| var == name) {
+            Some((_, value)) if raw => {
+                serde_json::from_str::<serde_json::Value>(value).map_err(|e| {
+                    anyhow::anyhow!(
+                        "--var {}: invalid JSON for {{{{#json {}}}}}: {}",
+                        name,
+                        name,
+                        e
+                    )
+                })?;
+                rendered.push_str(value);
+                used.insert(name);
+            }
+            Some((_, value)) => {
+                // the content of a JSON string: the value without the quotes
+                let quoted = serde_json::to_string(value)?;
+                rendered.push_str(&quoted[1..quoted.len() - 1]);
+                used.insert(name);
+            }
+            None => {
+                unresolved.insert(name);
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    if !unresolved.is_empty() {
+        anyhow::bail!(
+            "Unresolved placeholders in the query: {} (give their value with --var name=value)",
+            unresolved.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    for (name, _) in vars {
+        if !used.contains(name.as_str()) {
+            eprintln!("Warning: --var {} is not used by the query", name);
+        }
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_var, render};
+
+    fn vars(vars: &[&str]) -> Vec<(String, String)> {
+        vars.iter().map(|var| parse_var(var).unwrap()).collect()
+    }
+
+    #[test]
+    fn escapes_the_values_as_json_string_contents() {
+        let template = r#"{"query_string":{"query":"{{search}}"}}"#;
+        let rendered = render(template, &vars(&[r#"search=path:"C:\temp" OR "a\b""#])).unwrap();
+        assert_eq!(
+            rendered,
+            r#"{"query_string":{"query":"path:\"C:\\temp\" OR \"a\\b\""}}"#
+        );
+        let query: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(query["query_string"]["query"], r#"path:"C:\temp" OR "a\b""#);
+    }
+
+    #[test]
+    fn inserts_the_json_values_as_is() {
+        let template = r#"{"terms":{"tenant":{{#json tenants}}}}"#;
+        assert_eq!(
+            render(template, &vars(&[r#"tenants=["a","b"]"#])).unwrap(),
+            r#"{"terms":{"tenant":["a","b"]}}"#
+        );
+        let error = render(template, &vars(&["tenants=[a,b]"])).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("--var tenants: invalid JSON for {{#json tenants}}: "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn lists_the_unresolved_placeholders() {
+        let template = r#"{"range":{"{{field}}":{"lt":"{{cutoff}}","gte":"{{ from }}"}}}"#;
+        let error = render(template, &vars(&["from=now-1y", "unused=1"])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unresolved placeholders in the query: cutoff, field (give their value with --var \
+             name=value)"
+        );
+    }
+
+    #[test]
+    fn takes_the_last_value_of_a_variable() {
+        let rendered = render(
+            r#"{"term":{"level":"{{level}}"}}"#,
+            &vars(&["level=info", "level=debug=verbose"]),
+        )
+        .unwrap();
+        assert_eq!(rendered, r#"{"term":{"level":"debug=verbose"}}"#);
+    }
+
+    #[test]
+    fn parses_the_variables() {
+        assert_eq!(
+            parse_var("tenant.id=a=b").unwrap(),
+            ("tenant.id".to_string(), "a=b".to_string())
+        );
+        for invalid in ["tenant", "=a", "ten ant=a", "{{x}}=a"] {
+            assert!(parse_var(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! The terminal of stderr and the Windows console: the progress bar needs the ANSI escape codes,
+//! that a Windows console only interprets once asked to (legacy consoles never do), and closing
+//! the console window kills the process as soon as its handler returns.
+
+/// What stderr can show
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminal {
+    /// Redirected to a file or a pipe
+    Redirected,
+    /// A terminal without the ANSI escape codes: TERM=dumb, a legacy Windows console
+    Plain,
+    Ansi,
+}
+
+impl Terminal {
+    /// `term` is the TERM environment variable, `ansi` whether the console interprets the ANSI
+    /// escape codes (always outside of Windows)
+    pub fn detect(is_tty: bool, term: Option<&str>, ansi: bool) -> Self {
+        if !is_tty {
+            Terminal::Redirected
+        } else if !ansi || term == Some("dumb") {
+            Terminal::Plain
+        } else {
+            Terminal::Ansi
+        }
+    }
+
+    /// The terminal of stderr, with the ANSI escape codes enabled on a Windows console
+    pub fn stderr() -> Self {
+        let is_tty = atty::is(atty::Stream::Stderr);
+        Terminal::detect(
+            is_tty,
+            std::env::var("TERM").ok().as_deref(),
+            is_tty && enable_ansi(),
+        )
+    }
+}
+
+/// The run reported its outcome, a closed console no longer holds the process
+pub fn set_finished() {
+    #[cfg(windows)]
+    windows::FINISHED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(windows)]
+fn enable_ansi() -> bool {
+    windows::enable_ansi()
+}
+
+#[cfg(not(windows))]
+fn enable_ansi() -> bool {
+    true
+}
+
+/// Resolves when the console window is closed or the session ends, the process is then held
+/// until the run finished (or Windows gives up waiting, after 5s). Never outside of Windows,
+/// where the interrupt handler gets SIGHUP.
+pub async fn closed() {
+    #[cfg(windows)]
+    if let Some(closed) = windows::on_close() {
+        let _ = closed.await;
+        return;
+    }
+    std::future::pending::<()>().await
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::oneshot;
+    use winapi::{
+        shared::minwindef::{BOOL, DWORD, FALSE, TRUE},
+        um::{
+            consoleapi::{GetConsoleMode, SetConsoleCtrlHandler, SetConsoleMode},
+            processenv::GetStdHandle,
+            winbase::STD_ERROR_HANDLE,
+            wincon::{
+                CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT,
+                ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+            },
+        },
+    };
+
+    /// Windows kills the process 5s after a close event
+    const CLOSE_GRACE: Duration = Duration::from_millis(4500);
+
+    static CLOSED: Mutex<Option<oneshot::Sender<()>>> = Mutex::new(None);
+
+    /// Set once the run reported its outcome, nothing is left to wait for
+    pub static FINISHED: AtomicBool = AtomicBool::new(false);
+
+    /// Windows 10+ consoles interpret the ANSI escape codes once asked to
+    pub fn enable_ansi() -> bool {
+        // SAFETY: only the mode of the standard error console is read and written
+        unsafe {
+            let handle = GetStdHandle(STD_ERROR_HANDLE);
+            let mut mode = 0;
+            GetConsoleMode(handle, &mut mode) != 0
+                && (mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                    || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0)
+        }
+    }
+
+    /// Registered after the Ctrl-C handler, this handler is called first: it leaves Ctrl-C and
+    /// Ctrl-Break to it, whose handler would return at once on a close event
+    pub fn on_close() -> Option<oneshot::Receiver<()>> {
+        let (sender, receiver) = oneshot::channel();
+        *CLOSED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(sender);
+        // SAFETY: the handler is a plain function living as long as the process
+        (unsafe { SetConsoleCtrlHandler(Some(on_console_event), TRUE) } != 0).then_some(receiver)
+    }
+
+    unsafe extern "system" fn on_console_event(event: DWORD) -> BOOL {
+        if ![CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT].contains(&event) {
+            return FALSE;
+        }
+        let closed = CLOSED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        if let Some(closed) = closed {
+            let _ = closed.send(());
+        }
+        // the process is killed once the handler returns: give the tasks time to get cancelled
+        let started = Instant::now();
+        while !FINISHED.load(Ordering::SeqCst) && started.elapsed() < CLOSE_GRACE {
+            thread::sleep(Duration::from_millis(50));
+        }
+        TRUE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Terminal;
+
+    #[test]
+    fn draws_the_progress_bar_on_ansi_terminals_only() {
+        assert_eq!(
+            Terminal::detect(true, Some("xterm-256color"), true),
+            Terminal::Ansi
+        );
+        // a Windows console has no TERM
+        assert_eq!(Terminal::detect(true, None, true), Terminal::Ansi);
+        // a legacy Windows console that did not enable the escape codes
+        assert_eq!(Terminal::detect(true, None, false), Terminal::Plain);
+        assert_eq!(Terminal::detect(true, Some("dumb"), true), Terminal::Plain);
+        assert_eq!(
+            Terminal::detect(false, Some("xterm"), true),
+            Terminal::Redirected
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! The counters of the tasks sampled at each poll, without any extra request: summed up at the
+//! end of the run (average rate, gaps between batches, throttled time, slowest hour) and streamed
+//! to --timeline-csv as they come, so that a crash does not lose them.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Serialize, Serializer};
+
+use crate::{events::timestamp, format_duration, output::format_thousands, TaskId, TaskStatus};
+
+const HOUR_MILLIS: u64 = 3_600_000;
+/// Hours sampled for less than this are too short to tell their rate
+const MIN_HOUR_COVERAGE_MILLIS: u64 = 300_000;
+const CSV_HEADER: &str =
+    "ts,task,total,deleted,batches,version_conflicts,bulk_retries,requests_per_second,throttled_millis";
+
+/// The counters of a task when it was polled
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sample {
+    /// Milliseconds since the epoch
+    pub at_millis: u64,
+    pub task_id: TaskId,
+    pub total: u64,
+    pub deleted: u64,
+    pub batches: u64,
+    pub version_conflicts: u64,
+    pub bulk_retries: u64,
+    pub requests_per_second: f64,
+    pub throttled_millis: u64,
+}
+
+impl Sample {
+    pub fn now(task_id: &TaskId, status: &TaskStatus) -> Self {
+        Sample {
+            at_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            task_id: task_id.clone(),
+            total: status.total().max(0) as u64,
+            deleted: status.processed().max(0) as u64,
+            batches: status.batches.max(0) as u64,
+            version_conflicts: status.version_conflicts.max(0) as u64,
+            bulk_retries: status.retries.bulk.max(0) as u64,
+            requests_per_second: status.requests_per_second,
+            throttled_millis: status.throttled_millis.max(0) as u64,
+        }
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            timestamp(UNIX_EPOCH + Duration::from_millis(self.at_millis)),
+            self.task_id,
+            self.total,
+            self.deleted,
+            self.batches,
+            self.version_conflicts,
+            self.bulk_retries,
+            self.requests_per_second,
+            self.throttled_millis
+        )
+    }
+}
+
+/// The clock hour (UTC) that deleted the slowest
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SlowestHour {
+    pub start: String,
+    pub docs_per_sec: f64,
+}
+
+/// Where the time of the run went
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TimelineSummary {
+    pub avg_docs_per_sec: f64,
+    /// 95th percentile of the time between two batches, estimated from the batches counted
+    /// between polls
+    pub p95_batch_gap_secs: Option<f64>,
+    /// Time the tasks were running batches, and waiting for their throttle
+    pub active_secs: f64,
+    pub throttled_secs: f64,
+    /// Only when the run spans several hours
+    pub slowest_hour: Option<SlowestHour>,
+}
+
+impl TimelineSummary {
+    /// Summary of the samples in polling order, none without at least two polls. The counters
+    /// of each task start from 0, the samples of several tasks can be interleaved.
+    pub fn of(samples: &[Sample]) -> Option<Self> {
+        let span = samples
+            .last()?
+            .at_millis
+            .checked_sub(samples[0].at_millis)?;
+        if span == 0 {
+            return None;
+        }
+        // the previous sample of each task, and when its batch counter last moved
+        let mut tasks = HashMap::<&TaskId, (&Sample, u64)>::new();
+        // (gap, number of batches separated by that gap)
+        let mut gaps = Vec::new();
+        // deleted documents and sampled time of each hour
+        let mut hours = BTreeMap::<u64, (u64, u64)>::new();
+        let (mut deleted, mut throttled, mut active) = (0, 0, 0);
+        for sample in samples {
+            let (previous, batch_at) = match tasks.get_mut(&sample.task_id) {
+                Some(task) => task,
+                None => {
+                    // the task ran for an unknown time before its first poll
+                    deleted += sample.deleted;
+                    throttled += sample.throttled_millis;
+                    tasks.insert(&sample.task_id, (sample, sample.at_millis));
+                    continue;
+                }
+            };
+            let elapsed = sample.at_millis.saturating_sub(previous.at_millis);
+            let throttled_since = sample
+                .throttled_millis
+                .saturating_sub(previous.throttled_millis);
+            let deleted_since = sample.deleted.saturating_sub(previous.deleted);
+            deleted += deleted_since;
+            throttled += throttled_since;
+            active += elapsed.saturating_sub(throttled_since);
+            let hour = hours.entry(sample.at_millis / HOUR_MILLIS).or_default();
+            hour.0 += deleted_since;
+            hour.1 += elapsed;
+            let batches = sample.batches.saturating_sub(previous.batches);
+            if let Some(gap) = sample
+                .at_millis
+                .saturating_sub(*batch_at)
+                .checked_div(batches)
+            {
+                gaps.push((gap, batches));
+                *batch_at = sample.at_millis;
+            }
+            *previous = sample;
+        }
+        let slowest_hour = (hours.len() > 1)
+            .then(|| {
+                hours
+                    .iter()
+                    .filter(|(_, (_, covered))| *covered >= MIN_HOUR_COVERAGE_MILLIS)
+                    .map(|(hour, (deleted, covered))| {
+                        (*hour, *deleted as f64 * 1000.0 / *covered as f64)
+                    })
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+            })
+            .flatten()
+            .map(|(hour, docs_per_sec)| SlowestHour {
+                start: timestamp(UNIX_EPOCH + Duration::from_millis(hour * HOUR_MILLIS)),
+                docs_per_sec,
+            });
+        Some(TimelineSummary {
+            avg_docs_per_sec: deleted as f64 * 1000.0 / span as f64,
+            p95_batch_gap_secs: percentile(&mut gaps, 0.95).map(|gap| gap as f64 / 1000.0),
+            active_secs: active as f64 / 1000.0,
+            throttled_secs: throttled as f64 / 1000.0,
+            slowest_hour,
+        })
+    }
+}
+
+/// Percentile of weighted values, nearest rank
+fn percentile(values: &mut [(u64, u64)], rank: f64) -> Option<u64> {
+    values.sort_unstable();
+    let total = values.iter().map(|(_, weight)| weight).sum::<u64>();
+    let target = (total as f64 * rank).ceil() as u64;
+    let mut seen = 0;
+    values.iter().find_map(|(value, weight)| {
+        seen += weight;
+        (seen >= target).then_some(*value)
+    })
+}
+
+/// eg: 1,234 docs/s on average, p95 gap between batches 2s, active 3h10m, throttled 1h02m,
+/// slowest hour 2024-05-03T02:00 UTC (150 docs/s)
+impl fmt::Display for TimelineSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} docs/s on average",
+            format_thousands(self.avg_docs_per_sec.round() as u64)
+        )?;
+        if let Some(gap) = self.p95_batch_gap_secs {
+            write!(
+                f,
+                ", p95 gap between batches {}",
+                format_duration(Duration::from_secs_f64(gap))
+            )?;
+        }
+        write!(
+            f,
+            ", active {}, throttled {}",
+            format_duration(Duration::from_secs_f64(self.active_secs)),
+            format_duration(Duration::from_secs_f64(self.throttled_secs))
+        )?;
+        if let Some(hour) = &self.slowest_hour {
+            write!(
+                f,
+                ", slowest hour {} UTC ({} docs/s)",
+                &hour.start[..16],
+                format_thousands(hour.docs_per_sec.round() as u64)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The samples of a run summary are serialized as their summary
+pub fn serialize_summary<S: Serializer>(
+    samples: &[Sample],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    TimelineSummary::of(samples).serialize(serializer)
+}
+
+pub fn has_no_summary(samples: &[Sample]) -> bool {
+    TimelineSummary::of(samples).is_none()
+}
+
+/// --timeline-csv: each sample appended as a CSV row
+pub struct TimelineCsv {
+    path: PathBuf,
+    /// Opened on the first write, and again after a write error
+    file: Mutex<Option<File>>,
+}
+
+impl TimelineCsv {
+    pub fn new(path: PathBuf) -> Self {
+        TimelineCsv {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Errors are only reported: losing the timeline must not stop the deletion
+    pub fn append(&self, sample: &Sample) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let row = format!("{}\n", sample.csv_row());
+        let result = match &mut *file {
+            Some(file) => file.write_all(row.as_bytes()),
+            None => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|opened| {
+                    let file = file.insert(opened);
+                    // a file shared by several runs gets a single header
+                    if file.metadata()?.len() == 0 {
+                        writeln!(file, "{}", CSV_HEADER)?;
+                    }
+                    file.write_all(row.as_bytes())
+                }),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Warning: unable to write the timeline to {}: {}",
+                self.path.display(),
+                e
+            );
+            *file = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sample, TimelineSummary};
+    use crate::TaskId;
+
+    /// 2024-05-03T00:00:00Z
+    const START: u64 = 1_714_694_400_000;
+
+    fn sample(task: &str, secs: u64, deleted: u64, batches: u64, throttled_secs: u64) -> Sample {
+        Sample {
+            at_millis: START + secs * 1000,
+            task_id: TaskId::new(task).unwrap(),
+            total: 1_000_000,
+            deleted,
+            batches,
+            version_conflicts: 0,
+            bulk_retries: 0,
+            requests_per_second: 100.0,
+            throttled_millis: throttled_secs * 1000,
+        }
+    }
+
+    #[test]
+    fn needs_two_polls() {
+        assert_eq!(TimelineSummary::of(&[]), None);
+        assert_eq!(TimelineSummary::of(&[sample("node:1", 0, 0, 0, 0)]), None);
+    }
+
+    #[test]
+    fn sums_up_the_rate_the_gaps_and_the_throttled_time() {
+        let summary = TimelineSummary::of(&[
+            sample("node:1", 0, 0, 0, 0),
+            sample("node:1", 10, 1000, 1, 2),
+            // a retried task starts its counters from 0 again
+            sample("node:2", 10, 0, 0, 0),
+            sample("node:1", 20, 3000, 3, 4),
+            sample("node:2", 20, 500, 1, 0),
+        ])
+        .unwrap();
+        assert_eq!(
+            summary,
+            TimelineSummary {
+                avg_docs_per_sec: 175.0,
+                // 1 batch after 10s, then 2 batches 5s apart, and 1 batch after 10s
+                p95_batch_gap_secs: Some(10.0),
+                active_secs: 26.0,
+                throttled_secs: 4.0,
+                slowest_hour: None,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "175 docs/s on average, p95 gap between batches 10s, active 26s, throttled 4s"
+        );
+    }
+
+    #[test]
+    fn finds_the_slowest_hour() {
+        // a poll every 10 minutes: 100 docs/s during the first hour, 50 docs/s after
+        let samples = (0..=11u64)
+            .map(|poll| {
+                let deleted = 60_000 * poll.min(6) + 30_000 * poll.saturating_sub(6);
+                sample("node:1", poll * 600, deleted, poll, 0)
+            })
+            .collect::<Vec<_>>();
+        let summary = TimelineSummary::of(&samples).unwrap();
+        let slowest = summary.slowest_hour.unwrap();
+        // the poll at 01:00 still counts the faster minutes before it
+        assert_eq!(slowest.start, "2024-05-03T01:00:00.000Z");
+        assert_eq!(slowest.docs_per_sec.round(), 58.0);
+        assert_eq!(summary.p95_batch_gap_secs, Some(600.0));
+
+        // an hour sampled for a minute is not compared, even without any deletion
+        let mut samples = samples;
+        samples.push(sample("node:1", 7195, 539_750, 12, 0));
+        samples.push(sample("node:1", 7260, 539_750, 12, 0));
+        let slowest = TimelineSummary::of(&samples).unwrap().slowest_hour.unwrap();
+        assert_eq!(slowest.start, "2024-05-03T01:00:00.000Z");
+        assert_eq!(slowest.docs_per_sec.round(), 57.0);
+    }
+}
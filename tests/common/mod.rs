@@ -0,0 +1,198 @@
+//! A scripted Elasticsearch: an HTTP server on a local port answering each request with the
+//! response of a closure, and recording the requests to assert on them.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path and query string, eg: /logs/_delete_by_query?wait_for_completion=false
+    pub path: String,
+    /// Names in lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("the request body is not JSON")
+    }
+}
+
+type Responder = dyn FnMut(&Request) -> (u16, serde_json::Value) + Send;
+
+pub struct MockEs {
+    pub url: url::Url,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockEs {
+    /// Serve the responses of `respond` until the end of the test, a status 0 closes the
+    /// connection without any response
+    pub fn start(
+        respond: impl FnMut(&Request) -> (u16, serde_json::Value) + Send + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Mutex<Box<Responder>>> = Arc::new(Mutex::new(Box::new(respond)));
+        {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let requests = requests.clone();
+                    let respond = respond.clone();
+                    thread::spawn(move || serve(stream, &requests, &respond));
+                }
+            });
+        }
+        MockEs { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The requests to a path, without the query string
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<Request> {
+        self.requests()
+            .into_iter()
+            .filter(|request| {
+                request.method == method && request.path.split('?').next() == Some(path)
+            })
+            .collect()
+    }
+}
+
+/// One request per connection, closed after the response
+fn serve(stream: TcpStream, requests: &Mutex<Vec<Request>>, respond: &Mutex<Box<Responder>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut content_length = 0;
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap_or(0) == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    let request = Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    };
+    requests.lock().unwrap().push(request.clone());
+    let (status, body) = (respond.lock().unwrap())(&request);
+    if status == 0 {
+        return;
+    }
+    let body = body.to_string();
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\nx-elastic-product: Elasticsearch\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        if status < 400 { "OK" } else { "Error" },
+        body.len(),
+        body
+    );
+}
+
+/// A task of the tasks API, eg: `task("node:1", false, status(100, 40))`
+pub fn task(task_id: &str, completed: bool, status: serde_json::Value) -> serde_json::Value {
+    let (node, id) = task_id.split_once(':').unwrap();
+    serde_json::json!({
+        "completed": completed,
+        "task": {
+            "node": node,
+            "id": id.parse::<u64>().unwrap(),
+            "type": "transport",
+            "action": "indices:data/write/delete/byquery",
+            "status": status,
+            "description": "delete-by-query [logs]",
+            "start_time_in_millis": 1_700_000_000_000u64,
+            "running_time_in_nanos": 1_000_000_000u64,
+            "cancellable": true,
+            "headers": {}
+        }
+    })
+}
+
+/// The status of a delete by query task
+pub fn status(total: i64, deleted: i64) -> serde_json::Value {
+    serde_json::json!({
+        "total": total,
+        "updated": 0,
+        "created": 0,
+        "deleted": deleted,
+        "batches": 1,
+        "version_conflicts": 0,
+        "noops": 0,
+        "retries": { "bulk": 0, "search": 0 },
+        "throttled_millis": 0,
+        "requests_per_second": -1.0,
+        "throttled_until_millis": 0
+    })
+}
+
+/// A completed task with its response
+pub fn completed(
+    task_id: &str,
+    total: i64,
+    deleted: i64,
+    failures: serde_json::Value,
+) -> serde_json::Value {
+    let mut completed = task(task_id, true, status(total, deleted));
+    let mut response = status(total, deleted);
+    response["took"] = 1000.into();
+    response["timed_out"] = false.into();
+    response["throttled"] = "0s".into();
+    response["throttled_until"] = "0s".into();
+    response["failures"] = failures;
+    completed["response"] = response;
+    completed
+}
+
+/// An error as Elasticsearch answers it
+pub fn error(r#type: &str, reason: &str, status: u16) -> (u16, serde_json::Value) {
+    (
+        status,
+        serde_json::json!({
+            "error": {
+                "root_cause": [{ "type": r#type, "reason": reason }],
+                "type": r#type,
+                "reason": reason
+            },
+            "status": status
+        }),
+    )
+}
@@ -0,0 +1,861 @@
+#[allow(dead_code)]
+mod common;
+
+use std::process::{Command, Output};
+
+use common::{MockEs, Request};
+use elasticsearch_delete_by_query::Outcome;
+use serde_json::json;
+
+const QUERY: &str = r#"{"term":{"level":"debug"}}"#;
+
+/// Run the command line against `url`, without any state, credential lookup nor prompt
+fn run(url: &str, args: &[&str]) -> Output {
+    command().args(["--url", url]).args(args).output().unwrap()
+}
+
+/// The command line on the logs index, without any state, credential lookup nor prompt
+fn command() -> Command {
+    let cache = std::env::temp_dir().join(format!("esdbq-exit-codes-{}", std::process::id()));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_elasticsearch-delete-by-query"));
+    command
+        .args(["--index", "logs"])
+        .args([
+            "--no-state-file",
+            "--no-keyring",
+            "--no-netrc",
+            "--yes",
+            "--no-progress",
+            "--initial-wait",
+            "0",
+            "--poll-interval",
+            "1",
+        ])
+        .env("XDG_CACHE_HOME", cache);
+    command
+}
+
+/// A cluster where the delete completes on the first poll
+fn cluster(valid_query: bool) -> impl FnMut(&Request) -> (u16, serde_json::Value) {
+    move |request| {
+        let path = request.path.split('?').next().unwrap_or_default();
+        if path == "/" {
+            let version = json!({ "number": "7.17.0" });
+            (
+                200,
+                json!({ "cluster_name": "staging-1", "version": version }),
+            )
+        } else if path.ends_with("/_validate/query") {
+            (200, json!({ "valid": valid_query }))
+        } else if path.ends_with("/_count") {
+            (200, json!({ "count": 10 }))
+        } else if path.starts_with("/_resolve/index/") {
+            let index = json!({ "name": "logs", "attributes": ["open"] });
+            (
+                200,
+                json!({ "indices": [index], "aliases": [], "data_streams": [] }),
+            )
+        } else if path.starts_with("/_cat/indices") {
+            (
+                200,
+                json!([{ "index": "logs", "status": "open", "docs.count": "10" }]),
+            )
+        } else if path.starts_with("/_cluster/health") {
+            (200, json!({ "status": "green" }))
+        } else if path.ends_with("/_delete_by_query") {
+            (200, json!({ "task": "node:1" }))
+        } else if path.starts_with("/_tasks/") {
+            let status = json!({ "total": 10, "deleted": 10, "batches": 1 });
+            let response = json!({ "total": 10, "deleted": 10, "failures": [] });
+            let task = json!({ "node": "node", "id": 1, "status": status });
+            (
+                200,
+                json!({ "completed": true, "task": task, "response": response }),
+            )
+        } else {
+            (
+                404,
+                json!({ "error": { "reason": format!("no handler for {}", path) } }),
+            )
+        }
+    }
+}
+
+fn assert_exit_code(output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "stdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn maps_every_outcome_to_its_exit_code() {
+    let codes = [
+        (Outcome::Completed, 0),
+        (Outcome::DryRun, 0),
+        (Outcome::Preview, 0),
+        (Outcome::Detached, 0),
+        (Outcome::Skipped, 0),
+        (Outcome::Error, 1),
+        (Outcome::BadArguments, 2),
+        (Outcome::InvalidQuery, 3),
+        (Outcome::Unreachable, 4),
+        (Outcome::RetriesExhausted, 5),
+        (Outcome::TooManyConflicts, 6),
+        (Outcome::RefreshFailed, 7),
+        (Outcome::MatchesRemain, 8),
+        (Outcome::TaskNotFound, 9),
+        (Outcome::InterruptedTaskKept, 10),
+        (Outcome::ForceQuit, 11),
+        (Outcome::CancelFailed, 12),
+        (Outcome::TaskVanished, 13),
+        (Outcome::StatusUnavailable, 14),
+        (Outcome::TooManyMatches, 17),
+        (Outcome::PreflightFailed, 18),
+        (Outcome::DeadlineReached, 19),
+        (Outcome::ForceMergeFailed, 20),
+        (Outcome::WrongCluster, 21),
+        (Outcome::NothingMatched, 22),
+        (Outcome::UnexpectedCounters, 23),
+        (Outcome::NotConfirmed, 24),
+        (Outcome::Interrupted, 130),
+    ];
+    for (outcome, code) in codes {
+        // a new variant must be added to the table above, and to the README
+        match outcome {
+            Outcome::Completed
+            | Outcome::DryRun
+            | Outcome::Preview
+            | Outcome::Detached
+            | Outcome::Skipped
+            | Outcome::Error
+            | Outcome::NotConfirmed
+            | Outcome::InvalidQuery
+            | Outcome::Unreachable
+            | Outcome::BadArguments
+            | Outcome::RefreshFailed
+            | Outcome::MatchesRemain
+            | Outcome::TaskNotFound
+            | Outcome::InterruptedTaskKept
+            | Outcome::ForceQuit
+            | Outcome::CancelFailed
+            | Outcome::TaskVanished
+            | Outcome::StatusUnavailable
+            | Outcome::RetriesExhausted
+            | Outcome::TooManyConflicts
+            | Outcome::TooManyMatches
+            | Outcome::PreflightFailed
+            | Outcome::DeadlineReached
+            | Outcome::ForceMergeFailed
+            | Outcome::WrongCluster
+            | Outcome::NothingMatched
+            | Outcome::UnexpectedCounters
+            | Outcome::Interrupted => {}
+        }
+        assert_eq!(outcome.exit_code(), code, "{:?}", outcome);
+        assert_eq!(
+            serde_json::to_value(outcome).unwrap(),
+            outcome.to_string(),
+            "{:?}",
+            outcome
+        );
+    }
+}
+
+#[test]
+fn exits_with_the_code_of_the_outcome() {
+    let es = MockEs::start(cluster(true));
+    let output = run(es.url.as_str(), &[QUERY]);
+    assert_exit_code(&output, 0);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Outcome: completed (exit code 0)"));
+
+    let es = MockEs::start(cluster(false));
+    assert_exit_code(&run(es.url.as_str(), &[QUERY]), 3);
+}
+
+#[test]
+fn exits_with_a_distinct_code_before_running() {
+    assert_exit_code(&run("http://127.0.0.1:9", &["--no-such-option", "x"]), 2);
+    assert_exit_code(
+        &run("http://127.0.0.1:9", &["--query-file", "/nonexistent"]),
+        2,
+    );
+
+    // nothing listens on the discard port
+    let output = run("http://127.0.0.1:9", &[QUERY]);
+    assert_exit_code(&output, 4);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Outcome: unreachable (exit code 4)"));
+}
+
+#[test]
+fn refuses_to_run_on_the_wrong_cluster() {
+    let es = MockEs::start(cluster(true));
+    let output = run(es.url.as_str(), &["--expect-cluster", "prod-*", QUERY]);
+    assert_exit_code(&output, 21);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Refusing to run: the cluster is staging-1, not the expected prod-*"));
+    assert!(es.requests_to("POST", "/logs/_delete_by_query").is_empty());
+    assert_exit_code(
+        &run(es.url.as_str(), &["--expect-cluster", "staging-*", QUERY]),
+        0,
+    );
+
+    let config = std::env::temp_dir().join(format!("esdbq-protected-{}.toml", std::process::id()));
+    std::fs::write(&config, r#"protected_clusters = ["/staging-\\d+/"]"#).unwrap();
+    let config = config.to_str().unwrap();
+    assert_exit_code(&run(es.url.as_str(), &["--config", config, QUERY]), 21);
+    assert_exit_code(
+        &run(
+            es.url.as_str(),
+            &["--config", config, "--allow-protected-cluster", QUERY],
+        ),
+        0,
+    );
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 2);
+}
+
+#[test]
+fn refuses_to_delete_from_every_index_without_all_indices() {
+    let es = MockEs::start(cluster(true));
+    let output = run(es.url.as_str(), &["--index", "*", QUERY]);
+    assert_exit_code(&output, 24);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("targets EVERY index"));
+    assert_exit_code(
+        &run(es.url.as_str(), &["--index", "_all", "--dry-run", QUERY]),
+        0,
+    );
+    assert_exit_code(
+        &run(es.url.as_str(), &["--index", "*", "--all-indices", QUERY]),
+        0,
+    );
+}
+
+#[test]
+fn fails_if_the_query_matches_nothing() {
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        if request.path.split('?').next().unwrap_or_default() == "/logs/_count" {
+            (200, json!({ "count": 0 }))
+        } else {
+            cluster(request)
+        }
+    });
+    let output = run(es.url.as_str(), &["--fail-if-empty", QUERY]);
+    assert_exit_code(&output, 22);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("no document matches the query in indices matching 'logs'"));
+    assert!(es.requests_to("POST", "/logs/_delete_by_query").is_empty());
+    // without the option an empty match completes as before
+    assert_exit_code(&run(es.url.as_str(), &[QUERY]), 0);
+}
+
+#[test]
+fn checks_the_connection_before_running() {
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        if request.path == "/" {
+            (401, json!({ "error": "unauthorized" }))
+        } else {
+            cluster(request)
+        }
+    });
+    let output = run(es.url.as_str(), &[QUERY]);
+    assert_exit_code(&output, 4);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("the cluster rejected the credentials (HTTP 401 Unauthorized)"));
+    assert!(es.requests_to("POST", "/logs/_delete_by_query").is_empty());
+    assert_exit_code(
+        &run(
+            es.url.as_str(),
+            &["--skip-preflight", "--assume-version", "7.17", QUERY],
+        ),
+        0,
+    );
+
+    let es = MockEs::start(|_| (200, json!("<html>It works!</html>")));
+    let output = run(es.url.as_str(), &[QUERY]);
+    assert_exit_code(&output, 4);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not look like Elasticsearch"));
+}
+
+#[test]
+fn deletes_sorted_tranches() {
+    let es = MockEs::start(cluster(true));
+    let args = [
+        "--tranche-size",
+        "4",
+        "--max-tranches",
+        "2",
+        "--sort",
+        "@timestamp:asc",
+        QUERY,
+    ];
+    assert_exit_code(&run(es.url.as_str(), &args), 0);
+    let submits = es.requests_to("POST", "/logs/_delete_by_query");
+    assert_eq!(submits.len(), 2);
+    assert!(
+        submits[0].path.contains("max_docs=4"),
+        "{}",
+        submits[0].path
+    );
+    assert_eq!(submits[0].json()["sort"], json!([{ "@timestamp": "asc" }]));
+
+    // Elasticsearch 8 rejects the sort
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        if request.path == "/" {
+            let version = json!({ "number": "8.11.0" });
+            (
+                200,
+                json!({ "cluster_name": "staging-1", "version": version }),
+            )
+        } else {
+            cluster(request)
+        }
+    });
+    assert_exit_code(&run(es.url.as_str(), &args), 2);
+    assert!(es.requests_to("POST", "/logs/_delete_by_query").is_empty());
+}
+
+#[test]
+fn dumps_the_raw_responses() {
+    let es = MockEs::start(cluster(true));
+    let dir = std::env::temp_dir().join(format!("esdbq-dump-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = run(
+        es.url.as_str(),
+        &[
+            "--dump-task-responses",
+            dir.to_str().unwrap(),
+            "--dump-max-files",
+            "1",
+            QUERY,
+        ],
+    );
+    assert_exit_code(&output, 0);
+    // the submission was rotated out by the polls
+    let files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    assert!(files[0].ends_with("-task-node_1.json"), "{}", files[0]);
+    let dumped = std::fs::read_to_string(dir.join(&files[0])).unwrap();
+    assert!(dumped.contains("\n  \"completed\": true"), "{}", dumped);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn appends_an_audit_record_of_the_run() {
+    let es = MockEs::start(cluster(true));
+    let path = std::env::temp_dir().join(format!("esdbq-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let audit_file = path.to_str().unwrap();
+    let output = run(
+        es.url.as_str(),
+        &["--audit-file", audit_file, "--operator", "alice", QUERY],
+    );
+    assert_exit_code(&output, 0);
+    let records = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let record: serde_json::Value = serde_json::from_str(records.trim_end()).unwrap();
+    assert_eq!(record["operator"], "alice");
+    assert_eq!(record["operation"], "delete");
+    assert_eq!(record["outcome"], "completed");
+    assert_eq!(record["exit_code"], 0);
+    assert_eq!(record["deleted"], 10);
+    let cluster_run = &record["runs"][0];
+    assert_eq!(cluster_run["cluster"], "staging-1");
+    assert_eq!(cluster_run["index"], "logs");
+    assert_eq!(cluster_run["indices"], json!(["logs"]));
+    assert_eq!(
+        cluster_run["query"],
+        json!({ "term": { "level": "debug" } })
+    );
+    assert_eq!(cluster_run["task_ids"], json!(["node:1"]));
+
+    // an audit file that cannot be written stops the run before anything is submitted
+    let output = run(
+        es.url.as_str(),
+        &["--audit-file", "/nonexistent/audit.jsonl", QUERY],
+    );
+    assert_exit_code(&output, 2);
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[test]
+fn reattaches_to_the_task_of_a_previous_run_without_noting_clock_skew() {
+    let mut polls = 0;
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        // a task started hours ago, still running when the state file is read
+        if request.path.split('?').next().unwrap_or_default() == "/_tasks/node:7" {
+            polls += 1;
+            if polls == 1 {
+                (200, common::task("node:7", false, common::status(10, 4)))
+            } else {
+                (200, common::completed("node:7", 10, 10, json!([])))
+            }
+        } else {
+            cluster(request)
+        }
+    });
+    let query_hash = ring::digest::digest(&ring::digest::SHA256, QUERY.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let path = std::env::temp_dir().join(format!("esdbq-reattach-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        json!({
+            "url": es.url.as_str(),
+            "index": "logs",
+            "query_hash": query_hash,
+            "task_id": "node:7",
+            "deleted_total": 0
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_elasticsearch-delete-by-query"))
+        .args(["--url", es.url.as_str(), "--index", "logs"])
+        .args(["--state-file", path.to_str().unwrap(), "--resume"])
+        .args(["--no-keyring", "--no-netrc", "--yes", "--no-progress"])
+        .args(["--initial-wait", "0", "--poll-interval", "1", QUERY])
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_exit_code(&output, 0);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Re-attaching to task node:7"), "{}", stderr);
+    assert!(
+        !stderr.contains("clock of the cluster is about"),
+        "{}",
+        stderr
+    );
+    // the submission of the task is its start on the clock of the cluster
+    assert!(
+        stderr.contains("submitted 2023-11-14 22:13:20 UTC"),
+        "{}",
+        stderr
+    );
+    assert!(es.requests_to("POST", "/logs/_delete_by_query").is_empty());
+}
+
+#[test]
+fn notifies_the_end_of_the_run() {
+    let es = MockEs::start(cluster(true));
+    let webhook = MockEs::start(|_| (200, json!({})));
+    let hook = webhook.url.join("hook").unwrap();
+    let output = run(es.url.as_str(), &["--notify-webhook", hook.as_str(), QUERY]);
+    assert_exit_code(&output, 0);
+    let posts = webhook.requests_to("POST", "/hook");
+    assert_eq!(posts.len(), 1);
+    let notification = posts[0].json();
+    assert_eq!(notification["event"], "completed");
+    assert_eq!(notification["outcome"], "completed");
+    assert_eq!(notification["index"], "logs");
+    assert_eq!(notification["deleted"], 10);
+}
+
+#[test]
+fn reports_the_documents_deleted_from_an_index_before_it_got_blocked() {
+    let mut submits = 0;
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        match request.path.split('?').next().unwrap_or_default() {
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                (200, json!({ "task": format!("node:{}", submits) }))
+            }
+            // the first attempt deletes 30 documents before the cluster gets overloaded
+            "/_tasks/node:1" => (
+                200,
+                common::completed(
+                    "node:1",
+                    100,
+                    30,
+                    json!([{
+                        "index": "logs",
+                        "shard": 0,
+                        "node": "node",
+                        "reason": {
+                            "type": "es_rejected_execution_exception",
+                            "reason": "rejected execution of coordinating operation"
+                        }
+                    }]),
+                ),
+            ),
+            // the disk filled up meanwhile
+            "/_tasks/node:2" => (
+                200,
+                common::completed(
+                    "node:2",
+                    70,
+                    0,
+                    json!([{
+                        "index": "logs",
+                        "node": "node",
+                        "cause": {
+                            "type": "cluster_block_exception",
+                            "reason": "index [logs] blocked by: [TOO_MANY_REQUESTS/12/disk usage exceeded flood-stage watermark, index has read-only-allow-delete block];"
+                        }
+                    }]),
+                ),
+            ),
+            _ => cluster(request),
+        }
+    });
+    let output = run(
+        es.url.as_str(),
+        &["--per-index", "--pause-initial", "0", QUERY],
+    );
+    assert_exit_code(&output, 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("logs: 30 documents deleted"), "{}", stderr);
+    assert!(
+        stderr.contains("index logs has index.blocks.read_only_allow_delete set"),
+        "{}",
+        stderr
+    );
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 2);
+}
+
+/// A configuration file with a single `staging` profile, the command line of the run with it
+fn with_profile(name: &str, profile: &str) -> impl Fn(&[&str]) -> Command {
+    let path = std::env::temp_dir().join(format!(
+        "esdbq-exit-codes-{}-{}.toml",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, format!("[profiles.staging]\n{}\n", profile)).unwrap();
+    move |args| {
+        let mut command = command();
+        command
+            .arg("--config")
+            .arg(&path)
+            .args(["--profile", "staging"])
+            .args(args)
+            .arg(QUERY);
+        command
+    }
+}
+
+#[test]
+fn takes_the_cluster_from_the_command_line_then_the_environment_then_the_profile() {
+    let from_profile = MockEs::start(cluster(true));
+    let from_environment = MockEs::start(cluster(true));
+    let from_command_line = MockEs::start(cluster(true));
+    let run = with_profile("url", &format!("url = \"{}\"", from_profile.url));
+    let deletes = |es: &MockEs| es.requests_to("POST", "/logs/_delete_by_query").len();
+
+    assert_exit_code(&run(&[]).output().unwrap(), 0);
+    assert_eq!(deletes(&from_profile), 1);
+    let output = run(&[])
+        .env("ESDBQ_URL", from_environment.url.as_str())
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    assert_eq!(deletes(&from_environment), 1);
+    // the repeated options are not added to by the environment either
+    let output = run(&["--url", from_command_line.url.as_str()])
+        .env("ESDBQ_URL", from_environment.url.as_str())
+        .env("ESDBQ_INDEX", "metrics")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    assert_eq!(deletes(&from_command_line), 1);
+    assert_eq!((deletes(&from_profile), deletes(&from_environment)), (1, 1));
+}
+
+#[test]
+fn takes_the_credentials_from_the_command_line_then_the_environment_then_the_profile() {
+    let es = MockEs::start(cluster(true));
+    let run = with_profile(
+        "credentials",
+        &format!("url = \"{}\"\napi_key = \"profile-key\"", es.url),
+    );
+
+    assert_exit_code(&run(&[]).output().unwrap(), 0);
+    let output = run(&[])
+        .env("ESDBQ_USER", "elastic")
+        .env("ESDBQ_PASSWORD", "changeme")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let output = run(&["--api-key", "command-line-key"])
+        .env("ESDBQ_USER", "elastic")
+        .env("ESDBQ_PASSWORD", "changeme")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let authorizations = es
+        .requests_to("POST", "/logs/_delete_by_query")
+        .iter()
+        .map(|request| request.header("authorization").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        authorizations,
+        [
+            "ApiKey profile-key",
+            // elastic:changeme
+            "Basic ZWxhc3RpYzpjaGFuZ2VtZQ==",
+            "ApiKey command-line-key"
+        ]
+    );
+}
+
+#[test]
+fn still_reads_the_credentials_from_the_legacy_environment_variables() {
+    let es = MockEs::start(cluster(true));
+    let legacy = |args: &[&str]| {
+        let mut command = command();
+        command
+            .args(["--url", es.url.as_str()])
+            .args(args)
+            .arg(QUERY)
+            .env("ES_API_KEY", "legacy-key");
+        command
+    };
+
+    assert_exit_code(&legacy(&[]).output().unwrap(), 0);
+    let output = legacy(&[])
+        .env("ESDBQ_API_KEY", "environment-key")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let output = legacy(&["--api-key", "command-line-key"])
+        .env("ESDBQ_API_KEY", "environment-key")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    // the scheme given on the command line replaces the legacy one as well
+    let output = legacy(&["--user", "elastic"])
+        .env("ES_PASSWORD", "changeme")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let output = command()
+        .args(["--url", es.url.as_str()])
+        .arg(QUERY)
+        .env("ES_BEARER_TOKEN", "legacy-token")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let authorizations = es
+        .requests_to("POST", "/logs/_delete_by_query")
+        .iter()
+        .map(|request| request.header("authorization").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        authorizations,
+        [
+            "ApiKey legacy-key",
+            "ApiKey environment-key",
+            "ApiKey command-line-key",
+            // elastic:changeme
+            "Basic ZWxhc3RpYzpjaGFuZ2VtZQ==",
+            "Bearer legacy-token"
+        ]
+    );
+}
+
+#[test]
+fn refuses_several_authentication_schemes_left_by_the_environment() {
+    let es = MockEs::start(cluster(true));
+    let run = |args: &[&str], environment: &[(&str, &str)]| {
+        command()
+            .args(["--url", es.url.as_str()])
+            .args(args)
+            .arg(QUERY)
+            .envs(environment.iter().copied())
+            .output()
+            .unwrap()
+    };
+
+    for environment in [
+        [("ESDBQ_API_KEY", "key"), ("ESDBQ_USER", "elastic")],
+        [
+            ("ESDBQ_BEARER_TOKEN", "token"),
+            ("ESDBQ_BEARER_TOKEN_FILE", "token"),
+        ],
+        [("ES_API_KEY", "key"), ("ESDBQ_USER", "elastic")],
+    ] {
+        let output = run(&[], &environment);
+        assert_exit_code(&output, 2);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used together"));
+    }
+    // a scheme given on the command line replaces the ones of the environment
+    let environment = [("ESDBQ_API_KEY", "key"), ("ESDBQ_USER", "elastic")];
+    assert_exit_code(&run(&["--bearer-token", "token"], &environment), 0);
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[test]
+fn takes_the_throttle_from_the_command_line_then_the_environment_then_the_profile() {
+    let es = MockEs::start(cluster(true));
+    let run = with_profile(
+        "throttle",
+        &format!("url = \"{}\"\nrequests_per_second = 10", es.url),
+    );
+
+    assert_exit_code(&run(&[]).output().unwrap(), 0);
+    let output = run(&[])
+        .env("ESDBQ_REQUESTS_PER_SECOND", "20")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let output = run(&["--requests-per-seconds", "30"])
+        .env("ESDBQ_REQUESTS_PER_SECOND", "20")
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let throttles = es
+        .requests_to("POST", "/logs/_delete_by_query")
+        .iter()
+        .map(|request| {
+            url::Url::parse(&format!("http://es{}", request.path))
+                .unwrap()
+                .query_pairs()
+                .find(|(name, _)| name == "requests_per_second")
+                .map(|(_, value)| value.to_string())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        throttles,
+        [
+            Some("10".to_string()),
+            Some("20".to_string()),
+            Some("30".to_string())
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn cancel_reports_a_task_that_completed_before_the_cancel() {
+    let polls = std::sync::atomic::AtomicUsize::new(0);
+    let es = MockEs::start(move |request| match request.path.as_str() {
+        "/_tasks/node:1" if polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 => {
+            (200, common::task("node:1", false, common::status(10, 4)))
+        }
+        "/_tasks/node:1" | "/_tasks/node:1/_cancel" => common::error(
+            "resource_not_found_exception",
+            "task [node:1] isn't running and hasn't stored its results",
+            404,
+        ),
+        "/.tasks/_doc/node:1" => {
+            let result = common::completed("node:1", 10, 10, json!([]));
+            (200, json!({ "found": true, "_source": result }))
+        }
+        _ => common::error("unexpected", &request.path, 500),
+    });
+    let output = Command::new(env!("CARGO_BIN_EXE_elasticsearch-delete-by-query"))
+        .args(["--url", es.url.as_str(), "--no-keyring", "--no-netrc"])
+        .args(["cancel", "node:1"])
+        .output()
+        .unwrap();
+    assert_exit_code(&output, 0);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("Task node:1 had already completed: "),
+        "{}",
+        stdout
+    );
+    assert_eq!(es.requests_to("GET", "/.tasks/_doc/node:1").len(), 1);
+}
+
+#[test]
+fn cancels_the_task_on_sigterm() {
+    let cancelled = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let es = {
+        let cancelled = cancelled.clone();
+        let mut cluster = cluster(true);
+        MockEs::start(
+            move |request| match request.path.split('?').next().unwrap_or_default() {
+                "/_tasks/node:1/_cancel" => {
+                    cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+                    (200, json!({ "nodes": {} }))
+                }
+                "/_tasks/node:1" if !cancelled.load(std::sync::atomic::Ordering::SeqCst) => {
+                    (200, common::task("node:1", false, common::status(100, 10)))
+                }
+                _ => cluster(request),
+            },
+        )
+    };
+    let child = command()
+        .args(["--url", es.url.as_str(), QUERY])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    while es.requests_to("GET", "/_tasks/node:1").is_empty() {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(es.requests_to("POST", "/_tasks/node:1/_cancel").len(), 1);
+    assert_exit_code(&output, Outcome::Interrupted.exit_code());
+}
+
+/// The allow_no_indices parameter of the deletes
+fn allow_no_indices(es: &MockEs) -> Vec<String> {
+    es.requests_to("POST", "/logs/_delete_by_query")
+        .iter()
+        .filter_map(|request| {
+            url::Url::parse(&format!("http://es{}", request.path))
+                .unwrap()
+                .query_pairs()
+                .find(|(name, _)| name == "allow_no_indices")
+                .map(|(_, value)| value.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn sends_allow_no_indices_either_way() {
+    let es = MockEs::start(cluster(true));
+    assert_exit_code(&run(es.url.as_str(), &[QUERY]), 0);
+    assert_exit_code(&run(es.url.as_str(), &["--allow-no-indices", QUERY]), 0);
+    // ES defaults it to true
+    assert_eq!(allow_no_indices(&es), ["false", "true"]);
+}
+
+#[test]
+fn reports_a_missing_index_as_matching_nothing_without_wildcards() {
+    let mut cluster = cluster(true);
+    let es = MockEs::start(move |request| {
+        if request
+            .path
+            .split('?')
+            .next()
+            .unwrap()
+            .ends_with("/_delete_by_query")
+        {
+            common::error("index_not_found_exception", "no such index [logs]", 404)
+        } else {
+            cluster(request)
+        }
+    });
+    let output = run(es.url.as_str(), &[QUERY]);
+    assert_exit_code(&output, 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no indices matched 'logs'"), "{}", stderr);
+
+    // the patterns matching nothing are reported by the preflight, a missing index of an
+    // expression with wildcards is left as is
+    let output = run(es.url.as_str(), &["--index", "archive-*", QUERY]);
+    assert_exit_code(&output, 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("no indices matched"), "{}", stderr);
+    assert!(
+        stderr.contains("index_not_found_exception: no such index [logs]"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,812 @@
+//! The delete by query flows against a scripted Elasticsearch: submission, polling, retries,
+//! cancellation and errors.
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{completed, error, status, task, MockEs};
+use elasticsearch_delete_by_query::{DeleteByQueryJob, TaskId};
+use serde_json::json;
+
+fn fixture(name: &str) -> serde_json::Value {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+fn job(es: &MockEs) -> DeleteByQueryJob {
+    DeleteByQueryJob::new(es.url.clone(), "logs", json!({ "term": { "user": "bob" } }))
+        .unwrap()
+        .poll_interval(Duration::from_secs(1))
+        .initial_wait(Duration::ZERO)
+        .retry_pause(Duration::ZERO)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn polls_the_task_until_it_completes() {
+    let mut polls = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => {
+                polls += 1;
+                match polls {
+                    // the first status request only reads the X-Opaque-Id of the task
+                    1 | 2 => (200, task("node:1", false, status(100, 40))),
+                    _ => (200, completed("node:1", 100, 100, json!([]))),
+                }
+            }
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let outcome = {
+        let progress = progress.clone();
+        job(&es)
+            .on_progress(move |update| progress.lock().unwrap().push(update.deleted))
+            .run()
+            .await
+            .unwrap()
+    };
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.deleted, 100);
+    assert_eq!(outcome.attempts, 1);
+    assert_eq!(outcome.task_ids, vec![TaskId::new("node:1").unwrap()]);
+    assert!(!outcome.cancelled);
+    assert_eq!(*progress.lock().unwrap(), vec![40, 100]);
+    // on the clock of the cluster, from the start and running time of the task
+    let attempt = &outcome.per_attempt[0];
+    assert_eq!(
+        attempt.task_started_at.as_deref(),
+        Some("2023-11-14T22:13:20.000Z")
+    );
+    assert_eq!(
+        attempt.task_ended_at.as_deref(),
+        Some("2023-11-14T22:13:21.000Z")
+    );
+    assert!(attempt.submitted_at.is_some());
+
+    let submits = es.requests_to("POST", "/logs/_delete_by_query");
+    assert_eq!(submits.len(), 1);
+    let params = submits[0].path.split_once('?').unwrap().1;
+    assert!(params.contains("wait_for_completion=false"), "{}", params);
+    assert!(params.contains("conflicts=proceed"), "{}", params);
+    assert!(params.contains("stats=es-delete-by-query"), "{}", params);
+    assert_eq!(
+        submits[0].json(),
+        json!({ "query": { "term": { "user": "bob" } } })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_a_task_completed_with_failures() {
+    let mut submits = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                (200, json!({ "task": format!("node:{}", submits) }))
+            }
+            "/_tasks/node:1" => (
+                200,
+                completed(
+                    "node:1",
+                    100,
+                    30,
+                    json!([{
+                        "index": "logs",
+                        "shard": 0,
+                        "node": "node",
+                        "reason": {
+                            "type": "es_rejected_execution_exception",
+                            "reason": "rejected execution of coordinating operation"
+                        }
+                    }]),
+                ),
+            ),
+            "/_tasks/node:2" => (200, completed("node:2", 70, 70, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(2).run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.attempts, 2);
+    assert_eq!(outcome.deleted, 100);
+    assert_eq!(
+        outcome.task_ids,
+        vec![
+            TaskId::new("node:1").unwrap(),
+            TaskId::new("node:2").unwrap()
+        ]
+    );
+    assert_eq!(outcome.failures.len(), 1);
+    assert_eq!(
+        outcome.failures[0].r#type,
+        "es_rejected_execution_exception"
+    );
+    let per_attempt = &outcome.per_attempt;
+    assert_eq!(per_attempt.len(), 2);
+    assert_eq!((per_attempt[0].attempt, per_attempt[0].deleted), (1, 30));
+    assert_eq!(
+        per_attempt[0].failures,
+        vec!["es_rejected_execution_exception"]
+    );
+    assert_eq!((per_attempt[1].attempt, per_attempt[1].deleted), (2, 70));
+    assert!(per_attempt[1].failures.is_empty());
+    assert_eq!(per_attempt[1].retry_pause_secs, 0.0);
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lowers_the_throttle_of_the_attempts_after_rejections() {
+    let mut submits = 0;
+    let overloaded = |task_id, r#type| {
+        completed(
+            task_id,
+            100,
+            10,
+            json!([{
+                "index": "logs",
+                "shard": 0,
+                "node": "node",
+                "reason": { "type": r#type, "reason": "overloaded" }
+            }]),
+        )
+    };
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                (200, json!({ "task": format!("node:{}", submits) }))
+            }
+            "/_tasks/node:1" => (200, overloaded("node:1", "es_rejected_execution_exception")),
+            "/_tasks/node:2" => (200, overloaded("node:2", "circuit_breaking_exception")),
+            "/_tasks/node:3" => (200, completed("node:3", 80, 80, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es)
+        .requests_per_second(100.0)
+        .auto_throttle(30.0)
+        .max_retries(2)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(outcome.error, None);
+    // halved, then down to the floor
+    let throttles = [
+        "requests_per_second=100&",
+        "requests_per_second=50&",
+        "requests_per_second=30&",
+    ];
+    let submits = es.requests_to("POST", "/logs/_delete_by_query");
+    assert_eq!(submits.len(), 3);
+    for (submit, throttle) in submits.iter().zip(throttles) {
+        assert!(submit.path.contains(throttle), "{}", submit.path);
+    }
+    assert_eq!(
+        outcome
+            .per_attempt
+            .iter()
+            .map(|attempt| attempt.requests_per_second)
+            .collect::<Vec<_>>(),
+        vec![Some(100.0), Some(50.0), Some(30.0)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_on_failures_without_retries() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => (
+                200,
+                completed(
+                    "node:1",
+                    100,
+                    30,
+                    json!([{
+                        "index": "logs",
+                        "node": "node",
+                        "reason": { "type": "search_phase_execution_exception", "reason": "all shards failed" }
+                    }]),
+                ),
+            ),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(0).run().await.unwrap();
+    assert!(outcome.error.is_some());
+    assert_eq!(outcome.attempts, 1);
+    assert_eq!(outcome.deleted, 30);
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+/// A task of the flood-stage watermark: its deletions rejected by the read-only block
+fn blocked(task_id: &str) -> serde_json::Value {
+    completed(
+        task_id,
+        100,
+        0,
+        json!([{
+            "index": "logs",
+            "node": "node",
+            "cause": {
+                "type": "cluster_block_exception",
+                "reason": "index [logs] blocked by: [TOO_MANY_REQUESTS/12/disk usage exceeded flood-stage watermark, index has read-only-allow-delete block];"
+            }
+        }]),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_on_a_blocked_index_with_the_way_to_unblock_it() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => (200, blocked("node:1")),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(3).run().await.unwrap();
+    let error = outcome.error.unwrap();
+    assert!(
+        error.contains("index logs has index.blocks.read_only_allow_delete set"),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains(r#"PUT logs/_settings {"index.blocks.read_only_allow_delete": null}"#),
+        "{}",
+        error
+    );
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+    assert!(es.requests_to("PUT", "/logs/_settings").is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clears_the_flood_stage_block_and_retries() {
+    let mut submits = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                (200, json!({ "task": format!("node:{}", submits) }))
+            }
+            "/logs/_settings" => (200, json!({ "acknowledged": true })),
+            "/_tasks/node:1" => (200, blocked("node:1")),
+            "/_tasks/node:2" => (200, completed("node:2", 100, 100, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).clear_read_only_block().run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.deleted, 100);
+    let cleared = es.requests_to("PUT", "/logs/_settings");
+    assert_eq!(cleared.len(), 1);
+    assert_eq!(
+        cleared[0].json(),
+        json!({ "index.blocks.read_only_allow_delete": null })
+    );
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn groups_the_failures_by_index_and_type() {
+    let failures = (0..30)
+        .map(|shard| {
+            let (index, r#type) = match shard % 3 {
+                0 => ("logs-a", "mapper_parsing_exception"),
+                1 => ("logs-a", "illegal_argument_exception"),
+                _ => ("logs-b", "mapper_parsing_exception"),
+            };
+            json!({
+                "index": index,
+                "shard": shard,
+                "node": "node",
+                "reason": { "type": r#type, "reason": format!("failed on shard {}", shard) }
+            })
+        })
+        .chain((0..5).map(|shard| {
+            json!({
+                "index": "logs-c",
+                "shard": shard,
+                "node": "node",
+                "reason": { "type": "mapper_parsing_exception", "reason": "failed" }
+            })
+        }))
+        .collect::<Vec<_>>();
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => (200, completed("node:1", 100, 30, json!(failures))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(0).run().await.unwrap();
+    let error = outcome.error.expect("the delete should have failed");
+    let groups = error.lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        [
+            "  [permanent] logs-a — illegal_argument_exception ×10 (sample: failed on shard 1)",
+            "  [permanent] logs-a — mapper_parsing_exception ×10 (sample: failed on shard 0)",
+            "  [permanent] logs-b — mapper_parsing_exception ×10 (sample: failed on shard 2)",
+            "  [permanent] logs-c — mapper_parsing_exception ×5 (sample: failed)",
+        ],
+        "{}",
+        error
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_task_that_disappeared() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => error(
+                "resource_not_found_exception",
+                "task [node:1] isn't running and hasn't stored its results",
+                404,
+            ),
+            // the result was not stored either
+            "/.tasks/_doc/node:1" => (404, json!({ "found": false })),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(0).run().await.unwrap();
+    let error = outcome.error.expect("the delete should have failed");
+    assert!(error.contains("node:1"), "{}", error);
+    assert_eq!(es.requests_to("GET", "/.tasks/_doc/node:1").len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_error_of_a_rejected_submission() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => {
+                error("parsing_exception", "unknown query [terms_typo]", 400)
+            }
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    let error = outcome.error.expect("the delete should have failed");
+    assert!(error.contains("parsing_exception"), "{}", error);
+    assert!(error.contains("unknown query [terms_typo]"), "{}", error);
+    assert!(outcome.task_ids.is_empty());
+    // a rejected query is not submitted again
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_submission_without_a_task_id() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "<html>maintenance</html>" })),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    let error = outcome.error.expect("the delete should have failed");
+    assert!(error.contains("doesn't look like a task id"), "{}", error);
+    assert!(error.contains("maintenance"), "{}", error);
+    assert!(es
+        .requests_to("GET", "/_tasks/<html>maintenance</html>")
+        .is_empty());
+}
+
+/// The running tasks listed by GET _tasks?detailed=true: (task id, X-Opaque-Id, description)
+fn listed_tasks(tasks: &[(&str, &str, &str)]) -> serde_json::Value {
+    let tasks = tasks
+        .iter()
+        .map(|(task_id, opaque_id, description)| {
+            (
+                task_id.to_string(),
+                json!({
+                    "node": "node",
+                    "id": 1,
+                    "type": "transport",
+                    "action": "indices:data/write/delete/byquery",
+                    "description": description,
+                    "running_time_in_nanos": 1000,
+                    "cancellable": true,
+                    "headers": { "X-Opaque-Id": opaque_id }
+                }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    json!({ "nodes": { "node": { "name": "node", "tasks": tasks } } })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn follows_the_task_of_a_submission_that_dropped() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            // the task is created, the response is lost
+            "/logs/_delete_by_query" => (0, json!(null)),
+            "/_tasks" => {
+                let opaque_id = request.header("x-opaque-id").unwrap();
+                assert!(opaque_id.contains(" run="), "{}", opaque_id);
+                (
+                    200,
+                    listed_tasks(&[
+                        ("node:1", "someone else", "delete-by-query [logs]"),
+                        ("node:2", opaque_id, "delete-by-query [logs]"),
+                    ]),
+                )
+            }
+            "/_tasks/node:2" => (200, completed("node:2", 50, 50, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.task_ids, vec![TaskId::new("node:2").unwrap()]);
+    assert_eq!(outcome.deleted, 50);
+    // submitted when the cluster started it, not when it was found
+    assert_eq!(
+        outcome.per_attempt[0].submitted_at.as_deref(),
+        Some("2023-11-14T22:13:20.000Z")
+    );
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+    let list = &es.requests_to("GET", "/_tasks")[0];
+    assert!(
+        list.path.contains("actions=*byquery&detailed=true"),
+        "{}",
+        list.path
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn submits_again_when_the_dropped_submission_created_no_task() {
+    let mut submits = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                if submits == 1 {
+                    (0, json!(null))
+                } else {
+                    (200, json!({ "task": "node:3" }))
+                }
+            }
+            // another run deleting from the same index, and this run on another one
+            "/_tasks" => {
+                let opaque_id = request.header("x-opaque-id").unwrap();
+                (
+                    200,
+                    listed_tasks(&[
+                        (
+                            "node:1",
+                            "es-delete-by-query run=0",
+                            "delete-by-query [logs]",
+                        ),
+                        ("node:2", opaque_id, "delete-by-query [metrics]"),
+                    ]),
+                )
+            }
+            "/_tasks/node:3" => (200, completed("node:3", 50, 50, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.task_ids, vec![TaskId::new("node:3").unwrap()]);
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn completes_a_delete_answered_synchronously() {
+    let mut submits = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            // a gateway dropped wait_for_completion=false: the first delete ran to its end
+            "/logs/_delete_by_query" => {
+                submits += 1;
+                if submits == 1 {
+                    (
+                        200,
+                        completed(
+                            "node:1",
+                            100,
+                            40,
+                            json!([{
+                                "index": "logs",
+                                "shard": 0,
+                                "node": "node",
+                                "reason": {
+                                    "type": "es_rejected_execution_exception",
+                                    "reason": "rejected execution of coordinating operation"
+                                }
+                            }]),
+                        )["response"]
+                            .clone(),
+                    )
+                } else {
+                    (200, json!({ "task": "node:2" }))
+                }
+            }
+            "/_tasks/node:2" => (200, completed("node:2", 60, 60, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).max_retries(1).run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.deleted, 100);
+    assert_eq!(
+        outcome.task_ids,
+        vec![
+            TaskId::new("synchronous:1").unwrap(),
+            TaskId::new("node:2").unwrap()
+        ]
+    );
+    assert_eq!(
+        outcome.per_attempt[0].failures,
+        vec!["es_rejected_execution_exception"]
+    );
+    assert!(es.requests_to("GET", "/_tasks/synchronous:1").is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ignores_the_environment_of_the_command_line() {
+    std::env::set_var("ESDBQ_INDEX", "metrics");
+    std::env::set_var("ESDBQ_REQUESTS_PER_SECOND", "5");
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/-logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => (200, completed("node:1", 10, 10, json!([]))),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    // an index expression starting with a dash is not taken for an option
+    let outcome = DeleteByQueryJob::new(es.url.clone(), "-logs", json!({ "match_all": {} }))
+        .unwrap()
+        .initial_wait(Duration::ZERO)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(outcome.error, None);
+    let submits = es.requests_to("POST", "/-logs/_delete_by_query");
+    assert_eq!(submits.len(), 1);
+    assert!(
+        !submits[0].path.contains("requests_per_second"),
+        "{}",
+        submits[0].path
+    );
+}
+
+#[test]
+fn validates_task_ids() {
+    assert_eq!(
+        TaskId::new("oTUltX4IQMOUUVeiohTt8A:12345")
+            .unwrap()
+            .as_str(),
+        "oTUltX4IQMOUUVeiohTt8A:12345"
+    );
+    for invalid in ["", "12345", ":12345", "node:", "node:id", "node:1/_cancel"] {
+        assert!(TaskId::new(invalid).is_err(), "{}", invalid);
+    }
+}
+
+/// A delete through an alias with an ingest pipeline that turns it into updates
+fn updating(task_id: &str, done: bool, updated: i64) -> serde_json::Value {
+    let mut counters = status(100, 0);
+    counters["updated"] = updated.into();
+    let mut response = if done {
+        let mut response = completed(task_id, 100, 0, json!([]));
+        response["response"]["updated"] = updated.into();
+        response
+    } else {
+        task(task_id, false, status(100, 0))
+    };
+    response["task"]["status"] = counters;
+    response
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_documents_a_delete_updated() {
+    let mut polls = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => {
+                polls += 1;
+                (200, updating("node:1", polls > 1, polls * 10))
+            }
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.updated, 20);
+    assert!(es.requests_to("POST", "/_tasks/node:1/_cancel").is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancels_the_task_updating_documents_with_strict_counters() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1/_cancel" => (200, json!({ "nodes": {} })),
+            "/_tasks/node:1" => (200, updating("node:1", false, 10)),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es)
+        .strict_counters()
+        .max_retries(3)
+        .run()
+        .await
+        .unwrap();
+    let error = outcome.error.unwrap();
+    assert!(error.contains("10 updated"), "{}", error);
+    assert_eq!(es.requests_to("POST", "/_tasks/node:1/_cancel").len(), 1);
+    // not retried
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancels_the_task_on_the_cancellation_token() {
+    let cancelled = Arc::new(Mutex::new(false));
+    let es = {
+        let cancelled = cancelled.clone();
+        MockEs::start(
+            move |request| match request.path.split('?').next().unwrap() {
+                "/logs/_delete_by_query" => (200, json!({ "task": "node:1" })),
+                "/_tasks/node:1/_cancel" => {
+                    *cancelled.lock().unwrap() = true;
+                    (200, json!({ "nodes": {} }))
+                }
+                "/_tasks/node:1" if *cancelled.lock().unwrap() => {
+                    let mut response = completed("node:1", 100, 20, json!([]));
+                    response["response"]["canceled"] = "by user request".into();
+                    (200, response)
+                }
+                "/_tasks/node:1" => (200, task("node:1", false, status(100, 20))),
+                _ => error("unexpected", &request.path, 500),
+            },
+        )
+    };
+    let job = job(&es);
+    let token = job.cancellation_token();
+    let outcome = {
+        let token = token.clone();
+        job.on_progress(move |_| token.cancel())
+            .run()
+            .await
+            .unwrap()
+    };
+    assert!(outcome.cancelled);
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.deleted, 20);
+    assert_eq!(es.requests_to("POST", "/_tasks/node:1/_cancel").len(), 1);
+    // a cancelled delete is not submitted again
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn updates_with_a_script() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_update_by_query" => (200, json!({ "task": "node:1" })),
+            "/_tasks/node:1" => {
+                let mut response = completed("node:1", 10, 0, json!([]));
+                response["response"]["updated"] = 10.into();
+                (200, response)
+            }
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es)
+        .script("ctx._source.remove('email')")
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.deleted, 10);
+    let submits = es.requests_to("POST", "/logs/_update_by_query");
+    assert_eq!(submits.len(), 1);
+    assert_eq!(
+        submits[0].json()["script"],
+        json!({ "source": "ctx._source.remove('email')", "lang": "painless" })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn monitors_the_responses_of_a_real_cluster() {
+    let mut polls = 0;
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "r1A2WoRbTwKZ516z6NEs5A:36619" })),
+            "/_tasks/r1A2WoRbTwKZ516z6NEs5A:36619" => {
+                polls += 1;
+                match polls {
+                    1 | 2 => (200, fixture("running_sliced_task.json")),
+                    _ => (200, fixture("completed_with_failures.json")),
+                }
+            }
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let outcome = {
+        let progress = progress.clone();
+        job(&es)
+            .max_retries(0)
+            .on_progress(move |update| {
+                progress
+                    .lock()
+                    .unwrap()
+                    .push((update.deleted, update.total))
+            })
+            .run()
+            .await
+            .unwrap()
+    };
+    assert!(outcome.error.is_some());
+    assert_eq!(outcome.deleted, 3500);
+    assert_eq!(outcome.version_conflicts, 1);
+    assert_eq!(outcome.failures.len(), 2);
+    assert_eq!(*progress.lock().unwrap(), vec![(3000, 4980), (3500, 6154)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_error_of_a_failed_task() {
+    let es = MockEs::start(
+        move |request| match request.path.split('?').next().unwrap() {
+            "/logs/_delete_by_query" => (200, json!({ "task": "r1A2WoRbTwKZ516z6NEs5A:36620" })),
+            "/_tasks/r1A2WoRbTwKZ516z6NEs5A:36620" => (200, fixture("failed_task.json")),
+            _ => error("unexpected", &request.path, 500),
+        },
+    );
+    let outcome = job(&es).run().await.unwrap();
+    let error = outcome.error.expect("the delete should have failed");
+    assert!(
+        error.contains(
+            "search_phase_execution_exception: all shards failed, caused by query_shard_exception"
+        ),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains("caused by too_many_clauses: maxClauseCount is set to 1024"),
+        "{}",
+        error
+    );
+    // a permanent failure is not retried
+    assert_eq!(es.requests_to("POST", "/logs/_delete_by_query").len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn monitors_the_tasks_of_every_supported_version() {
+    for (name, deleted) in [
+        ("es_6_8_completed.json", 2200),
+        ("es_8_completed.json", 14990),
+        ("opensearch_2_completed.json", 480),
+    ] {
+        let completed = fixture(name);
+        let task_id = format!(
+            "{}:{}",
+            completed["task"]["node"].as_str().unwrap(),
+            completed["task"]["id"]
+        );
+        let es = {
+            let task_id = task_id.clone();
+            MockEs::start(
+                move |request| match request.path.split('?').next().unwrap() {
+                    "/logs/_delete_by_query" => (200, json!({ "task": task_id })),
+                    path if path == format!("/_tasks/{}", task_id) => (200, completed.clone()),
+                    _ => error("unexpected", &request.path, 500),
+                },
+            )
+        };
+        let outcome = job(&es).run().await.unwrap();
+        assert_eq!(outcome.error, None, "{}", name);
+        assert_eq!(outcome.deleted, deleted, "{}", name);
+        assert_eq!(outcome.task_ids, vec![TaskId::new(&task_id).unwrap()]);
+    }
+}
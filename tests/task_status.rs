@@ -0,0 +1,132 @@
+//! Deserialization of the task statuses and failures, pinned on responses of real clusters.
+
+use std::time::Duration;
+
+use elasticsearch_delete_by_query::{Failure, FailureReport, TaskStatus};
+
+fn fixture(name: &str) -> serde_json::Value {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+fn status_of(value: &serde_json::Value) -> TaskStatus {
+    serde_json::from_value(value.clone()).unwrap()
+}
+
+#[test]
+fn sums_the_slices_of_a_sliced_task() {
+    let task = fixture("running_sliced_task.json");
+    let status = status_of(&task["task"]["status"]);
+    // the parent reports nothing while its slices run
+    assert_eq!(status.deleted, 0);
+    assert_eq!(status.total(), 4980);
+    assert_eq!(status.processed(), 3000);
+    assert_eq!(status.slices.len(), 3);
+    assert!(status.slices[1].is_none());
+}
+
+#[test]
+fn reads_the_response_of_a_completed_task() {
+    let task = fixture("completed_with_failures.json");
+    let status = status_of(&task["response"]);
+    assert_eq!(status.total(), 6154);
+    assert_eq!(status.processed(), 3500);
+    assert_eq!(status.version_conflicts, 1);
+    assert_eq!(status.retries.search, 2);
+    assert_eq!(status.throttled_millis, 1250);
+    assert_eq!(status.requests_per_second, 500.0);
+    assert_eq!(status.canceled, None);
+}
+
+#[test]
+fn reads_bulk_and_search_failures() {
+    let task = fixture("completed_with_failures.json");
+    let failures: Vec<Failure> =
+        serde_json::from_value(task["response"]["failures"].clone()).unwrap();
+    assert_eq!(failures.len(), 2);
+    // a bulk failure has an id and a cause
+    assert_eq!(failures[0].id.as_deref(), Some("kz8HxosBx0pMH3cG2fJc"));
+    assert_eq!(
+        failures[0].reason.r#type,
+        "version_conflict_engine_exception"
+    );
+    assert_eq!(failures[0].shard, None);
+    // a search failure a shard and a reason
+    assert_eq!(failures[1].shard, Some(2));
+    assert_eq!(
+        FailureReport::from(&failures[1]),
+        FailureReport {
+            index: Some("logs-2023.11".to_string()),
+            node: Some("r1A2WoRbTwKZ516z6NEs5A".to_string()),
+            r#type: "es_rejected_execution_exception".to_string(),
+            reason: failures[1].reason.reason.clone(),
+        }
+    );
+}
+
+#[test]
+fn counts_the_updated_documents_of_an_update_by_query() {
+    let task = fixture("completed_update_by_query.json");
+    let status = status_of(&task["task"]["status"]);
+    assert_eq!(status.updated, 118);
+    assert_eq!(status.processed(), 118);
+    assert_eq!(status.noops, 2);
+    assert_eq!(status.canceled.as_deref(), Some("by user request"));
+}
+
+#[test]
+fn reads_the_statuses_of_every_supported_version() {
+    // (fixture, total, processed), the 6.8 response has no throttled/throttled_until
+    for (name, total, processed) in [
+        ("es_6_8_completed.json", 2200, 2200),
+        ("es_8_completed.json", 15000, 14990),
+        ("opensearch_2_completed.json", 480, 480),
+    ] {
+        let task = fixture(name);
+        for status in [&task["task"]["status"], &task["response"]] {
+            let status = status_of(status);
+            assert_eq!(status.total(), total, "{}", name);
+            assert_eq!(status.processed(), processed, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn sums_the_slices_of_a_parent_without_counters() {
+    let task = fixture("es_7_17_running_sliced.json");
+    let status = status_of(&task["task"]["status"]);
+    assert_eq!(status.total(), 2100);
+    assert_eq!(status.processed(), 1000);
+    // a slice reporting only some counters
+    let slice = status.slices[1].as_ref().unwrap();
+    assert_eq!((slice.slice_id, slice.updated), (1, 0));
+}
+
+#[test]
+fn counts_down_to_the_next_throttled_batch() {
+    let status = status_of(&serde_json::json!({
+        "total": 1000,
+        "requests_per_second": 10.0,
+        "throttled_until_millis": 4200
+    }));
+    let countdown = |millis| status.throttle_countdown(Duration::from_millis(millis));
+    assert_eq!(
+        countdown(0).as_deref(),
+        Some("throttled, next batch in ~5s")
+    );
+    assert_eq!(
+        countdown(1500).as_deref(),
+        Some("throttled, next batch in ~3s")
+    );
+    assert_eq!(
+        countdown(5000).as_deref(),
+        Some("throttled, next batch due")
+    );
+    assert_eq!(
+        status.next_batch_in(Duration::from_secs(1)),
+        Some(Duration::from_millis(3200))
+    );
+
+    let running = status_of(&serde_json::json!({ "total": 1000, "throttled_until_millis": 0 }));
+    assert_eq!(running.throttle_countdown(Duration::ZERO), None);
+}